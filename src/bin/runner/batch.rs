@@ -0,0 +1,91 @@
+//! Non-interactive battles, reported the way `pmars -b` does
+use {
+  libcw::{
+    game::{BattleOutcome, Match},
+    MarsBuilder, Warrior,
+  },
+  options::Options,
+};
+
+/// Fight every round of the battle and print the results, see `report`
+pub fn run(options: &Options, builder: MarsBuilder, warriors: &[Warrior]) -> Result<(), String> {
+  print!("{}", report(options, builder, warriors)?);
+  Ok(())
+}
+
+/// Fight every round of the battle and return the results
+///
+/// Each warrior gets a line with its score, followed by a line of the number
+/// of rounds each warrior won and then the number of rounds that were tied
+fn report(options: &Options, builder: MarsBuilder, warriors: &[Warrior]) -> Result<String, String> {
+  let mut battle =
+    Match::with_seed(builder, options.rounds, options.seed).map_err(|e| e.to_string())?;
+
+  let mut pins = vec![];
+  for (i, warrior) in warriors.iter().enumerate() {
+    let pin = match options.fixed_address(i) {
+      Some(address) => battle.add_player(warrior.clone(), address),
+      None => battle.add_player_rand(warrior.clone()),
+    };
+    pins.push(pin.map_err(|e| e.to_string())?);
+  }
+
+  let mut ties = 0;
+  while !battle.is_finished() {
+    if let BattleOutcome::Tie(_) = battle.run_round().map_err(|e| e.to_string())? {
+      ties += 1;
+    }
+  }
+
+  let mut report = String::new();
+  for (warrior, &pin) in warriors.iter().zip(&pins) {
+    report.push_str(&format!(
+      "{} by {} scores {}\n",
+      warrior.name.as_ref().map_or("Anonymous", String::as_str),
+      warrior.author.as_ref().map_or("Anonymous", String::as_str),
+      battle.score(pin).expect("the player was added")
+    ));
+  }
+
+  let wins: Vec<String> = pins
+    .iter()
+    .map(|&pin| battle.record(pin).expect("the player was added").wins)
+    .map(|wins| wins.to_string())
+    .collect();
+  report.push_str(&format!("Results: {} {}\n", wins.join(" "), ties));
+  Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use libcw::parse_program;
+
+  fn fight(args: &str, warriors: &[Warrior]) -> String {
+    let options = Options::parse(args.split_whitespace().map(str::to_string))
+      .unwrap()
+      .unwrap();
+    report(&options, options.builder(), warriors).unwrap()
+  }
+
+  #[test]
+  fn report_results() {
+    let imp = parse_program(";name Imp\n;author A. K. Dewdney\nMOV.I 0, 1").unwrap();
+    let dat = parse_program(";name Sitting Duck\nDAT #0, #0").unwrap();
+
+    assert_eq!(
+      fight(
+        "-b -r 3 -s 800 -c 200 -l 10 -d 10 -F 400 imp dat",
+        &[imp.clone(), dat]
+      ),
+      "Imp by A. K. Dewdney scores 9\nSitting Duck by Anonymous scores 0\nResults: 3 0 0\n"
+    );
+
+    // every round between two imps is tied, with both surviving
+    let report = fight(
+      "-b -r 2 -s 800 -c 200 -l 10 -d 10 imp imp",
+      &[imp.clone(), imp],
+    );
+    assert!(report.ends_with("Results: 0 0 2\n"), "{}", report);
+  }
+}
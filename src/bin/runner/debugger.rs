@@ -0,0 +1,339 @@
+//! Interactive debugger for stepping through a battle
+use {
+  libcw::{game::Game, parse_program_with_environment, Address, Environment, Instruction},
+  std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, BufRead, Write},
+  },
+};
+
+/// Number of cells shown around the program counter
+const MEMORY_VIEW_SIZE: usize = 17;
+
+pub const HELP: &str = "commands:
+  step [N]          execute N instructions (default 1), also an empty line
+  back [N]          undo the last N instructions (default 1)
+  continue          run until a breakpoint, a watched cell changes or the end
+  break ADDR        set or clear a breakpoint on an address
+  watch ADDR        set or clear a watch on an address
+  list [FROM TO]    print memory, by default around the program counter
+  pid               print the process and address that execute next
+  threads           print the thread queue of every process
+  pspace            print the P-space of every process
+  set ADDR INSTR    write an instruction to memory, e.g. `set 10 dat #0, #0`,
+                    after which earlier steps can't be undone
+  quit              stop debugging
+  help              print this message";
+
+#[derive(Debug, Clone, PartialEq)]
+enum Command {
+  Step(usize),
+  Back(usize),
+  Continue,
+  Break(i64),
+  Watch(i64),
+  List(Option<(i64, i64)>),
+  Pid,
+  Threads,
+  PSpace,
+  Set(i64, String),
+  Quit,
+  Help,
+}
+
+impl Command {
+  fn parse(line: &str) -> Result<Command, String> {
+    let line = line.trim();
+    let (name, rest) = match line.find(char::is_whitespace) {
+      Some(i) => (&line[..i], line[i..].trim()),
+      None => (line, ""),
+    };
+    let args: Vec<&str> = rest.split_whitespace().collect();
+    let number = |arg: &str| {
+      arg
+        .parse::<i64>()
+        .map_err(|_| format!("`{}` is not a number", arg))
+    };
+    let count = |arg: &str| {
+      arg
+        .parse::<usize>()
+        .map_err(|_| format!("`{}` is not a count of instructions", arg))
+    };
+
+    let command = match (name, args.as_slice()) {
+      ("", []) | ("step", []) | ("s", []) => Command::Step(1),
+      ("step", [n]) | ("s", [n]) => Command::Step(count(n)?),
+      ("back", []) => Command::Back(1),
+      ("back", [n]) => Command::Back(count(n)?),
+      ("continue", []) | ("c", []) => Command::Continue,
+      ("break", [address]) | ("b", [address]) => Command::Break(number(address)?),
+      ("watch", [address]) | ("w", [address]) => Command::Watch(number(address)?),
+      ("list", []) | ("l", []) => Command::List(None),
+      ("list", [from, to]) | ("l", [from, to]) => Command::List(Some((number(from)?, number(to)?))),
+      ("pid", []) => Command::Pid,
+      ("threads", []) => Command::Threads,
+      ("pspace", []) => Command::PSpace,
+      ("set", [address, _, ..]) => {
+        let instruction = rest[address.len()..].trim();
+        Command::Set(number(address)?, instruction.to_string())
+      }
+      ("quit", []) | ("q", []) => Command::Quit,
+      ("help", []) | ("h", []) => Command::Help,
+      _ => return Err(format!("invalid command `{}`, try `help`", line)),
+    };
+    Ok(command)
+  }
+}
+
+/// Why running stopped before the requested number of steps
+enum Stop {
+  Breakpoint(Address),
+  Watch(Address),
+  Finished,
+}
+
+pub struct Debugger {
+  game: Game,
+  env: Environment,
+  breakpoints: BTreeSet<Address>,
+  /// Watched addresses and the contents they had after the last step
+  watches: BTreeMap<Address, Instruction>,
+}
+
+impl Debugger {
+  pub fn new(game: Game, env: Environment) -> Self {
+    Debugger {
+      game,
+      env,
+      breakpoints: BTreeSet::new(),
+      watches: BTreeMap::new(),
+    }
+  }
+
+  /// Read and execute commands until `quit` or the end of the input
+  pub fn run(&mut self) -> io::Result<()> {
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    let mut line = String::new();
+
+    self.print_status();
+    loop {
+      print!("> ");
+      stdout.flush()?;
+      line.clear();
+      if stdin.lock().read_line(&mut line)? == 0 {
+        return Ok(());
+      }
+
+      match Command::parse(&line) {
+        Ok(Command::Quit) => return Ok(()),
+        Ok(command) => self.execute(command),
+        Err(e) => println!("{}", e),
+      }
+    }
+  }
+
+  fn execute(&mut self, command: Command) {
+    match command {
+      Command::Step(steps) => self.run_for(Some(steps)),
+      Command::Back(steps) => {
+        let undone = (0..steps)
+          .take_while(|_| self.game.step_back().is_some())
+          .count();
+        if undone < steps && self.game.mars().cycle() == 0 {
+          println!("the start of the battle was reached");
+        } else if undone < steps {
+          println!("older steps are forgotten, no more can be undone");
+        }
+        self.update_watches();
+        self.print_status();
+      }
+      Command::Continue => self.run_for(None),
+      Command::Break(address) => {
+        let address = self.fold(address);
+        if self.breakpoints.remove(&address) {
+          println!("cleared breakpoint at {:04}", address);
+        } else {
+          self.breakpoints.insert(address);
+          println!("breakpoint at {:04}", address);
+        }
+      }
+      Command::Watch(address) => {
+        let address = self.fold(address);
+        if self.watches.remove(&address).is_some() {
+          println!("cleared watch on {:04}", address);
+        } else {
+          let instruction = self.game.mars().memory()[address as usize];
+          self.watches.insert(address, instruction);
+          println!("watching {:04}", address);
+        }
+      }
+      Command::List(None) => self.print_memory_view(),
+      Command::List(Some((from, to))) => {
+        let size = self.game.mars().size() as i64;
+        // show at most the whole core, even if `to` wraps past `from`
+        let count = ((to - from).rem_euclid(size) + 1) as usize;
+        let from = self.fold(from);
+        for address in (from as usize..).take(count) {
+          self.print_cell(address as Address % size as Address);
+        }
+      }
+      Command::Pid => match (self.game.mars().pid(), self.game.mars().pc()) {
+        (Some(pid), Some(pc)) => println!("pid {} executes {:04} next", pid, pc),
+        _ => println!("no processes left"),
+      },
+      Command::Threads => {
+        for (pid, queue) in self.game.mars().process_queues() {
+          let queue: Vec<String> = queue.map(|pc| format!("{:04}", pc)).collect();
+          println!("{:>4}: {}", pid, queue.join(" "));
+        }
+      }
+      Command::PSpace => {
+        for (pid, pspace) in self.game.mars().process_pspaces() {
+          let pspace: Vec<String> = pspace.iter().map(Address::to_string).collect();
+          println!("{:>4}: {}", pid, pspace.join(" "));
+        }
+      }
+      Command::Set(address, source) => {
+        let address = self.fold(address);
+        match parse_program_with_environment(&source, &self.env) {
+          Ok(ref warrior) if warrior.instructions.len() == 1 => {
+            self
+              .game
+              .mars_mut()
+              .set_memory(&warrior.instructions, address);
+            self.update_watches();
+            self.print_cell(address);
+          }
+          Ok(_) => println!("expected a single instruction"),
+          Err(e) => println!("{}", e),
+        }
+      }
+      Command::Help => println!("{}", HELP),
+      Command::Quit => {}
+    }
+  }
+
+  /// Step `steps` times, or until the battle ends if `None`, stopping early
+  /// at breakpoints and changes to watched cells
+  fn run_for(&mut self, steps: Option<usize>) {
+    let mut stop = None;
+    let mut remaining = steps;
+
+    while remaining != Some(0) {
+      if self.game.is_finished() {
+        stop = Some(Stop::Finished);
+        break;
+      }
+      self.game.step();
+      remaining = remaining.map(|n| n - 1);
+
+      if let Some(&address) = self
+        .watches
+        .iter()
+        .find(|&(&address, instruction)| {
+          self.game.mars().memory()[address as usize] != *instruction
+        })
+        .map(|(address, _)| address)
+      {
+        stop = Some(Stop::Watch(address));
+        break;
+      }
+      if let Some(pc) = self
+        .game
+        .mars()
+        .pc()
+        .filter(|pc| self.breakpoints.contains(pc))
+      {
+        stop = Some(Stop::Breakpoint(pc));
+        break;
+      }
+    }
+    self.update_watches();
+
+    match stop {
+      Some(Stop::Breakpoint(address)) => println!("breakpoint at {:04}", address),
+      Some(Stop::Watch(address)) => println!("{:04} changed", address),
+      Some(Stop::Finished) => println!("the battle is over"),
+      None => {}
+    }
+    self.print_status();
+  }
+
+  fn update_watches(&mut self) {
+    let memory = self.game.mars().memory();
+    for (&address, instruction) in &mut self.watches {
+      *instruction = memory[address as usize];
+    }
+  }
+
+  /// Fold an address that may be negative into the core
+  fn fold(&self, address: i64) -> Address {
+    address.rem_euclid(self.game.mars().size() as i64) as Address
+  }
+
+  fn print_status(&self) {
+    let mars = self.game.mars();
+    match (mars.pid(), mars.pc()) {
+      (Some(pid), Some(pc)) => println!(
+        "|CYCLE: {:06} | PC: {:04} | PID: {:04} |",
+        mars.cycle(),
+        pc,
+        pid
+      ),
+      _ => println!("|CYCLE: {:06} | no processes left |", mars.cycle()),
+    }
+    self.print_memory_view();
+  }
+
+  fn print_memory_view(&self) {
+    let size = self.game.mars().size();
+    let pc = self.game.mars().pc().unwrap_or(0) as usize;
+    let before = (MEMORY_VIEW_SIZE - 1) / 2 % size;
+    for address in (size + pc - before..).take(MEMORY_VIEW_SIZE.min(size)) {
+      self.print_cell((address % size) as Address);
+    }
+  }
+
+  fn print_cell(&self, address: Address) {
+    let mars = self.game.mars();
+    let marker = if mars.pc() == Some(address) { '>' } else { ' ' };
+    let breakpoint = if self.breakpoints.contains(&address) {
+      '*'
+    } else {
+      ' '
+    };
+    println!(
+      "{}{}[{:04}] {}",
+      marker,
+      breakpoint,
+      address,
+      mars.memory()[address as usize]
+    );
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn parse_commands() {
+    assert_eq!(Command::parse("\n"), Ok(Command::Step(1)));
+    assert_eq!(Command::parse("step 20"), Ok(Command::Step(20)));
+    assert_eq!(Command::parse("back"), Ok(Command::Back(1)));
+    assert_eq!(Command::parse("break -3"), Ok(Command::Break(-3)));
+    assert_eq!(
+      Command::parse("list 10 20"),
+      Ok(Command::List(Some((10, 20))))
+    );
+    assert_eq!(
+      Command::parse("set 4 mov.i 0, 1"),
+      Ok(Command::Set(4, "mov.i 0, 1".to_string()))
+    );
+    assert!(Command::parse("list 10").is_err());
+    assert!(Command::parse("step x").is_err());
+    assert!(Command::parse("step -1").is_err());
+    assert!(Command::parse("back -1").is_err());
+  }
+}
@@ -0,0 +1,178 @@
+extern crate libcw;
+extern crate rand;
+
+mod batch;
+mod debugger;
+mod options;
+mod replay;
+mod trace;
+mod tui;
+
+use {
+  debugger::{Debugger, HELP},
+  libcw::{
+    game::{BattleOutcome, Game, Pin},
+    parse_program_collecting_errors, Address, Environment, MarsBuilder, Warrior,
+  },
+  options::{Options, USAGE},
+  rand::{rngs::StdRng, SeedableRng},
+  std::{env, fs, io, process},
+};
+
+fn main() -> io::Result<()> {
+  let options = match Options::parse(env::args().skip(1)) {
+    Ok(Some(options)) => options,
+    Ok(None) => {
+      println!("{}", USAGE);
+      return Ok(());
+    }
+    Err(e) => {
+      eprintln!("{}\n\n{}", e, USAGE);
+      process::exit(2)
+    }
+  };
+
+  if let Some(ref path) = options.replay {
+    if let Err(e) = replay::run(path) {
+      eprintln!("{}", e);
+      process::exit(1)
+    }
+    return Ok(());
+  }
+
+  let builder = options.builder();
+  if let Err(e) = builder.build() {
+    eprintln!("{}", e);
+    process::exit(2)
+  }
+  let mut env = Environment::from(&builder);
+  env.warriors = options.paths.len();
+  env.rounds = options.rounds;
+  let warriors = load_warriors(&options, &env);
+
+  if options.batch {
+    if let Err(e) = batch::run(&options, builder, &warriors) {
+      eprintln!("{}", e);
+      process::exit(1)
+    }
+    return Ok(());
+  }
+
+  if let Some(ref path) = options.trace {
+    let (game, placements) = new_game(&options, builder, &warriors);
+    if let Err(e) = trace::run(path, options.trace_format, game, &placements, &warriors) {
+      eprintln!("{}", e);
+      process::exit(1)
+    }
+    return Ok(());
+  }
+
+  if let Some(ref path) = options.save_replay {
+    let (game, placements) = new_game(&options, builder, &warriors);
+    let seed = if options.position.is_none() {
+      Some(options.seed)
+    } else {
+      None
+    };
+    if let Err(e) = replay::save(path, game, seed, &placements, &warriors) {
+      eprintln!("{}", e);
+      process::exit(1)
+    }
+    return Ok(());
+  }
+
+  // the core map is coloured by the last process to write each cell, and the
+  // debugger can step backwards
+  let builder = builder.track_cells(options.tui).reversible(!options.tui);
+  let (game, placements) = new_game(&options, builder, &warriors);
+  if options.tui {
+    tui::run(&options, game, &placements, &warriors)
+  } else {
+    println!("{}\n", HELP);
+    Debugger::new(game, env).run()
+  }
+}
+
+/// Parse every warrior on the command line, exiting on the first file that
+/// can't be read or has errors
+fn load_warriors(options: &Options, env: &Environment) -> Vec<Warrior> {
+  options
+    .paths
+    .iter()
+    .map(|path| {
+      let source = fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("failed to read {}: {}", path, e);
+        process::exit(1)
+      });
+      let warrior = parse_program_collecting_errors(&source, env).unwrap_or_else(|errors| {
+        for error in errors {
+          eprintln!("{}: {}\n", path, error);
+        }
+        process::exit(1)
+      });
+      if warrior.instructions.len() > options.length {
+        eprintln!(
+          "{}: {} instructions is longer than the maximum of {}",
+          path,
+          warrior.instructions.len(),
+          options.length
+        );
+        process::exit(1)
+      }
+      warrior
+    })
+    .collect()
+}
+
+/// Load the warriors into a game for a single round
+///
+/// # Returns
+/// The game along with the pin and load address of each warrior
+fn new_game(
+  options: &Options,
+  builder: MarsBuilder,
+  warriors: &[Warrior],
+) -> (Game, Vec<(Pin, Address)>) {
+  let mut rng = StdRng::seed_from_u64(options.seed);
+  let mut game = Game::new(builder.build().expect("the builder was validated"));
+  let mut placements = vec![];
+  for (i, warrior) in warriors.iter().enumerate() {
+    let address = match options.fixed_address(i) {
+      Some(address) => Ok(address),
+      None => game.random_address(warrior.instructions.len(), &mut rng),
+    };
+    match address.and_then(|address| Ok((game.add_warrior(warrior, address)?, address))) {
+      Ok(placement) => placements.push(placement),
+      Err(e) => {
+        eprintln!("{}", e);
+        process::exit(1)
+      }
+    }
+  }
+  (game, placements)
+}
+
+/// Describe the outcome of a round, naming the pin of each placement after the
+/// warrior at the same index
+fn describe_outcome(
+  outcome: &BattleOutcome,
+  placements: &[(Pin, Address)],
+  warriors: &[Warrior],
+) -> String {
+  let name = |pin: &Pin| {
+    placements
+      .iter()
+      .zip(warriors)
+      .find(|&(&(placed, _), _)| placed == *pin)
+      .and_then(|(_, warrior)| warrior.name.as_ref())
+      .map_or("Anonymous", String::as_str)
+  };
+  match outcome {
+    BattleOutcome::Win(pin) => format!("{} wins", name(pin)),
+    BattleOutcome::Tie(pins) => {
+      let pins: Vec<&str> = pins.iter().map(name).collect();
+      format!("tie between {}", pins.join(", "))
+    }
+    BattleOutcome::AllDead => "every warrior died".to_string(),
+  }
+}
@@ -0,0 +1,199 @@
+//! Command line options, which follow pMARS where it has an equivalent
+use libcw::{
+  Address, MarsBuilder, TraceFormat, MARS_DEFAULT_MAX_CYCLES, MARS_DEFAULT_MAX_LENGTH,
+  MARS_DEFAULT_MAX_PROCESSES, MARS_DEFAULT_MIN_DISTANCE, MARS_DEFAULT_P_SPACE_SIZE,
+  MARS_DEFAULT_SIZE,
+};
+
+pub const USAGE: &str = "usage: runner [options] warrior...
+       runner --replay FILE
+
+options:
+  -b           run every round to completion and print the results
+  --tui        draw the core while a round runs
+  --speed N    cycles per second drawn by --tui (default 2000)
+  --trace FILE run a round to completion, writing every executed instruction
+               to FILE
+  --json       write the trace as JSON lines instead of text
+  --save-replay FILE
+               run a round to completion and save it to FILE
+  --replay FILE
+               fight the round saved in FILE again instead of loading
+               warriors, failing if it ends differently
+  -r ROUNDS    number of rounds to fight (default 1)
+  -s SIZE      size of the core (default 8000)
+  -c CYCLES    cycles each warrior gets before a round is a tie (default 80000)
+  -p PROCESSES maximum number of threads per warrior (default 8000)
+  -l LENGTH    maximum length of a warrior (default 100)
+  -d DISTANCE  minimum distance between warriors (default 100)
+  -S SIZE      size of each warrior's P-space (default 8)
+  -F POSITION  load warrior N at N * POSITION instead of at random
+  --seed SEED  seed for random positions
+  -h, --help   print this message";
+
+#[derive(Debug, Clone)]
+pub struct Options {
+  pub batch: bool,
+  pub tui: bool,
+  /// Cycles per second drawn in the TUI
+  pub speed: usize,
+  /// File to write the trace of a round to
+  pub trace: Option<String>,
+  pub trace_format: TraceFormat,
+  /// File to save the replay of a round to
+  pub save_replay: Option<String>,
+  /// File of a replay to fight again
+  pub replay: Option<String>,
+  pub rounds: usize,
+  pub size: usize,
+  pub cycles: usize,
+  pub processes: usize,
+  pub length: usize,
+  pub distance: usize,
+  pub p_space_size: usize,
+  /// Distance between fixed positions, or `None` for random positions
+  pub position: Option<Address>,
+  pub seed: u64,
+  pub paths: Vec<String>,
+}
+
+impl Default for Options {
+  fn default() -> Self {
+    Options {
+      batch: false,
+      tui: false,
+      speed: 2000,
+      trace: None,
+      trace_format: TraceFormat::Text,
+      save_replay: None,
+      replay: None,
+      rounds: 1,
+      size: MARS_DEFAULT_SIZE,
+      cycles: MARS_DEFAULT_MAX_CYCLES,
+      processes: MARS_DEFAULT_MAX_PROCESSES,
+      length: MARS_DEFAULT_MAX_LENGTH,
+      distance: MARS_DEFAULT_MIN_DISTANCE,
+      p_space_size: MARS_DEFAULT_P_SPACE_SIZE,
+      position: None,
+      seed: 0,
+      paths: vec![],
+    }
+  }
+}
+
+impl Options {
+  /// Parse the command line arguments, not including the program name
+  ///
+  /// # Returns
+  /// `Ok(None)` if help was requested
+  pub fn parse<I>(args: I) -> Result<Option<Options>, String>
+  where
+    I: IntoIterator<Item = String>,
+  {
+    let mut options = Options {
+      seed: ::rand::random(),
+      ..Options::default()
+    };
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+      let mut value = || {
+        args
+          .next()
+          .ok_or_else(|| format!("missing value for {}", arg))
+      };
+      let number = |value: String| {
+        value
+          .parse::<u64>()
+          .map_err(|_| format!("invalid value for {}: {}", arg, value))
+      };
+
+      match arg.as_str() {
+        "-h" | "--help" => return Ok(None),
+        "-b" => options.batch = true,
+        "--tui" => options.tui = true,
+        "--trace" => options.trace = Some(value()?),
+        "--json" => options.trace_format = TraceFormat::JsonLines,
+        "--save-replay" => options.save_replay = Some(value()?),
+        "--replay" => options.replay = Some(value()?),
+        "--speed" => options.speed = number(value()?)? as usize,
+        "-r" => options.rounds = number(value()?)? as usize,
+        "-s" => options.size = number(value()?)? as usize,
+        "-c" => options.cycles = number(value()?)? as usize,
+        "-p" => options.processes = number(value()?)? as usize,
+        "-l" => options.length = number(value()?)? as usize,
+        "-d" => options.distance = number(value()?)? as usize,
+        "-S" => options.p_space_size = number(value()?)? as usize,
+        "-F" => options.position = Some(number(value()?)? as Address),
+        "--seed" => options.seed = number(value()?)?,
+        _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+        _ => options.paths.push(arg),
+      }
+    }
+
+    if options.speed == 0 {
+      return Err("--speed must be at least 1".to_string());
+    }
+    if options.paths.is_empty() && options.replay.is_none() {
+      return Err("no warriors given".to_string());
+    }
+    Ok(Some(options))
+  }
+
+  /// Return the load address of warrior `i` when warriors are loaded at
+  /// fixed positions, wrapped into the core, or `None` for random positions
+  pub fn fixed_address(&self, i: usize) -> Option<Address> {
+    self
+      .position
+      .map(|position| (i as u64 * u64::from(position) % self.size as u64) as Address)
+  }
+
+  pub fn builder(&self) -> MarsBuilder {
+    MarsBuilder::new(self.size)
+      .max_cycles(self.cycles)
+      .max_processes(self.processes)
+      .max_length(self.length)
+      .min_distance(self.distance)
+      .p_space_size(self.p_space_size)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn parse(args: &str) -> Result<Option<Options>, String> {
+    Options::parse(args.split_whitespace().map(str::to_string))
+  }
+
+  #[test]
+  fn parse_options() {
+    let options = parse("-b -r 10 -s 800 -F 400 --seed 7 imp.red dwarf.red")
+      .unwrap()
+      .unwrap();
+    assert!(options.batch);
+    assert_eq!(options.rounds, 10);
+    assert_eq!(options.size, 800);
+    assert_eq!(options.position, Some(400));
+    assert_eq!(options.seed, 7);
+    assert_eq!(options.paths, vec!["imp.red", "dwarf.red"]);
+    assert_eq!(options.cycles, MARS_DEFAULT_MAX_CYCLES);
+
+    assert!(parse("-h imp.red").unwrap().is_none());
+    assert!(parse("--replay round.txt").unwrap().is_some());
+    assert!(parse("-b").is_err());
+    assert!(parse("-r imp.red").is_err());
+    assert!(parse("-s").is_err());
+    assert!(parse("--speed 0 imp.red").is_err());
+    assert!(parse("-x imp.red").is_err());
+  }
+
+  #[test]
+  fn fixed_addresses_wrap() {
+    let options = parse("-s 800 -F 4000000100 imp.red").unwrap().unwrap();
+    assert_eq!(options.fixed_address(0), Some(0));
+    assert_eq!(options.fixed_address(1), Some(100));
+    assert_eq!(options.fixed_address(3), Some(300));
+    assert_eq!(parse("imp.red").unwrap().unwrap().fixed_address(1), None);
+  }
+}
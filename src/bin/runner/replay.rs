@@ -0,0 +1,54 @@
+//! Replays of a round, which fight it again exactly as it was fought
+use {
+  describe_outcome,
+  libcw::{
+    game::{Game, Pin},
+    replay::Replay,
+    Address, Warrior,
+  },
+  std::path::Path,
+};
+
+/// Run the round to completion, saving its replay to `path` along with the
+/// seed of the random load addresses, if they were random, and print the
+/// outcome
+pub fn save(
+  path: &str,
+  mut game: Game,
+  seed: Option<u64>,
+  placements: &[(Pin, Address)],
+  warriors: &[Warrior],
+) -> Result<(), String> {
+  let outcome = game.run_to_completion();
+  let mut replay = game.replay();
+  if let Some(seed) = seed {
+    replay.set_seed(seed);
+  }
+  replay.save(Path::new(path)).map_err(|e| e.to_string())?;
+
+  println!("{}", describe_outcome(&outcome, placements, warriors));
+  Ok(())
+}
+
+/// Fight the round saved in `path` again and print the outcome
+///
+/// # Errors
+/// Fails if the replay can't be read or the round ends differently than it
+/// did when it was saved
+pub fn run(path: &str) -> Result<(), String> {
+  let mut replay = Replay::load(Path::new(path)).map_err(|e| e.to_string())?;
+  let outcome = replay.run().map_err(|e| e.to_string())?;
+
+  let placements: Vec<(Pin, Address)> = replay
+    .players()
+    .iter()
+    .map(|player| (player.pin, player.address))
+    .collect();
+  let warriors: Vec<Warrior> = replay
+    .players()
+    .iter()
+    .map(|player| player.warrior.clone())
+    .collect();
+  println!("{}", describe_outcome(&outcome, &placements, &warriors));
+  Ok(())
+}
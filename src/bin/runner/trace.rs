@@ -0,0 +1,29 @@
+//! Traces of a round, for comparing runs with other simulators like pMARS
+use {
+  describe_outcome,
+  libcw::{
+    game::{Game, Pin},
+    Address, TraceFormat, Tracer, Warrior,
+  },
+  std::{fs::File, io::BufWriter},
+};
+
+/// Run the round to completion, writing every executed instruction to `path`,
+/// and print the outcome
+pub fn run(
+  path: &str,
+  format: TraceFormat,
+  mut game: Game,
+  placements: &[(Pin, Address)],
+  warriors: &[Warrior],
+) -> Result<(), String> {
+  let file = File::create(path).map_err(|e| format!("failed to create {}: {}", path, e))?;
+  let mut tracer = Tracer::new(BufWriter::new(file), format);
+  let outcome = game.run_to_completion_with(&mut tracer);
+  tracer
+    .finish()
+    .map_err(|e| format!("failed to write {}: {}", path, e))?;
+
+  println!("{}", describe_outcome(&outcome, placements, warriors));
+  Ok(())
+}
@@ -0,0 +1,177 @@
+//! Full-screen view of the core, drawn with ANSI escape codes
+use {
+  describe_outcome,
+  libcw::{
+    game::{Game, Pin},
+    Address, OpCode, Pid, Warrior,
+  },
+  options::Options,
+  std::{
+    collections::HashMap,
+    env,
+    fmt::Write as FmtWrite,
+    io::{self, Write},
+    thread,
+    time::{Duration, Instant},
+  },
+};
+
+/// Width of the core map when the terminal doesn't export `COLUMNS`
+const DEFAULT_WIDTH: usize = 100;
+/// Longest time between frames
+const FRAME_TIME: Duration = Duration::from_millis(40);
+/// Number of program counters listed for each process
+const LISTED_PCS: usize = 8;
+/// Foreground colours given to the pins in turn
+const COLOURS: &[u8] = &[32, 31, 34, 33, 35, 36];
+
+const CLEAR_SCREEN: &str = "\x1b[2J";
+const CURSOR_HOME: &str = "\x1b[H";
+const RESET: &str = "\x1b[0m";
+
+/// Run a single round to completion, redrawing the core `options.speed`
+/// times a second at most. The Mars must track the history of its cells
+pub fn run(
+  options: &Options,
+  mut game: Game,
+  placements: &[(Pin, Address)],
+  warriors: &[Warrior],
+) -> io::Result<()> {
+  let width = env::var("COLUMNS")
+    .ok()
+    .and_then(|columns| columns.parse().ok())
+    .unwrap_or(DEFAULT_WIDTH);
+  let frames_per_second = 1_000 / FRAME_TIME.as_millis() as usize;
+  let cycles_per_frame = (options.speed / frames_per_second).max(1);
+  let frame_time = FRAME_TIME.max(Duration::from_millis(1_000 / options.speed as u64));
+
+  let names: HashMap<Pin, &str> = placements
+    .iter()
+    .zip(warriors)
+    .map(|(&(pin, _), warrior)| {
+      (
+        pin,
+        warrior.name.as_ref().map_or("Anonymous", String::as_str),
+      )
+    })
+    .collect();
+  let pins: HashMap<Pid, Pin> = game
+    .pins_with_pids()
+    .map(|(&pin, &pid)| (pid, pin))
+    .collect();
+  let stdout = io::stdout();
+  let mut stdout = stdout.lock();
+
+  write!(stdout, "{}", CLEAR_SCREEN)?;
+  loop {
+    let start = Instant::now();
+    let finished = (0..cycles_per_frame).any(|_| {
+      if game.is_finished() {
+        return true;
+      }
+      game.step();
+      false
+    });
+
+    stdout.write_all(draw(&game, &pins, &names, width).as_bytes())?;
+    stdout.flush()?;
+    if finished || game.is_finished() {
+      break;
+    }
+    if let Some(left) = frame_time.checked_sub(start.elapsed()) {
+      thread::sleep(left);
+    }
+  }
+
+  let outcome = game.run_to_completion();
+  writeln!(
+    stdout,
+    "{}",
+    describe_outcome(&outcome, placements, warriors)
+  )
+}
+
+/// Draw a frame: a status line, a line for each process and the core map
+fn draw(game: &Game, pins: &HashMap<Pid, Pin>, names: &HashMap<Pin, &str>, width: usize) -> String {
+  let mars = game.mars();
+  let mut pcs: HashMap<Address, Pin> = HashMap::new();
+  let mut frame = String::from(CURSOR_HOME);
+
+  // writing to a string can't fail
+  let _ = writeln!(
+    frame,
+    "cycle {:>7}   cycles left {:>7}\x1b[K",
+    mars.cycle(),
+    mars.cycles_left()
+  );
+  let mut sorted: Vec<&Pin> = names.keys().collect();
+  sorted.sort();
+  for &pin in sorted {
+    let queue = mars
+      .process_queues()
+      .find(|&(pid, _)| pins.get(&pid) == Some(&pin))
+      .map(|(_, queue)| queue.cloned().collect::<Vec<_>>());
+    let _ = write!(
+      frame,
+      "{}#{} {:<20}{}",
+      colour(pin),
+      pin,
+      names[&pin],
+      RESET
+    );
+    match queue {
+      Some(queue) => {
+        for &pc in &queue {
+          pcs.insert(pc, pin);
+        }
+        let listed: Vec<String> = queue
+          .iter()
+          .take(LISTED_PCS)
+          .map(|pc| format!("{:04}", pc))
+          .collect();
+        let more = if queue.len() > LISTED_PCS { " ..." } else { "" };
+        let _ = writeln!(
+          frame,
+          " threads {:>5}   pc {}{}\x1b[K",
+          queue.len(),
+          listed.join(" "),
+          more
+        );
+      }
+      None => {
+        let _ = writeln!(frame, " dead\x1b[K");
+      }
+    }
+  }
+
+  let history = mars
+    .cell_history()
+    .expect("the core map is drawn from a Mars that tracks cells");
+  for (address, (instruction, cell)) in mars.memory().iter().zip(history).enumerate() {
+    if address > 0 && address % width == 0 {
+      frame.push('\n');
+    }
+    // colour each cell by the player that last wrote it
+    let owner = cell.write.and_then(|write| pins.get(&write.pid));
+    match (pcs.get(&(address as Address)), owner) {
+      (Some(&pin), _) => {
+        let _ = write!(frame, "\x1b[1;7m{}@{}", colour(pin), RESET);
+      }
+      (None, Some(&pin)) => {
+        let glyph = if instruction.op.code == OpCode::Dat {
+          'x'
+        } else {
+          '#'
+        };
+        let _ = write!(frame, "{}{}{}", colour(pin), glyph, RESET);
+      }
+      (None, None) => frame.push('.'),
+    }
+  }
+  frame.push_str("\n\x1b[J");
+  frame
+}
+
+fn colour(pin: Pin) -> String {
+  format!("\x1b[{}m", COLOURS[pin % COLOURS.len()])
+}
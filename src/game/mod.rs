@@ -1,16 +1,41 @@
-use failure::{Error, Fail};
-use itertools::Either;
+mod rounds;
+
+pub use self::rounds::{Match, Record};
+
+use failure::Fail;
 use rand::Rng;
-use redcode::{Address, Instruction};
-use simulation::{Mars, Pid};
+use redcode::{Address, Instruction, Warrior};
+use replay::{Player, Replay};
+use simulation::{Mars, MarsError, Observer, PSpace, Pid, StepOutcome};
 use std::collections::HashMap;
 
 pub type Pin = usize;
 
-#[derive(Debug, Clone, Copy, Fail)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Fail)]
 pub enum GameError {
   #[fail(display = "conflicting pin: {}", pin)]
   PinConflict { pin: Pin },
+  #[fail(display = "no room in the core for a program of length {}", length)]
+  NoRoom { length: usize },
+  #[fail(display = "{}", _0)]
+  Mars(#[cause] MarsError),
+}
+
+impl From<MarsError> for GameError {
+  fn from(error: MarsError) -> Self {
+    GameError::Mars(error)
+  }
+}
+
+/// The result of a battle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BattleOutcome {
+  /// The player with the pin outlived every other player
+  Win(Pin),
+  /// The cycle limit was reached with these players still alive
+  Tie(Vec<Pin>),
+  /// Every player was eliminated
+  AllDead,
 }
 
 /// Corewars game runtime that wraps a `Mars` to provide additional information
@@ -19,11 +44,29 @@ pub enum GameError {
 pub struct Game {
   // associate player pins with
   pin_to_pid: HashMap<Pin, Pid>,
+  /// Every player as it was loaded, to replay the game
+  players: Vec<Player>,
+  /// Eliminated players along with the cycle they were eliminated in
+  eliminations: Vec<(Pin, usize)>,
   mars: Mars,
 }
 
 impl Game {
+  /// Create a game that is played in `mars`
+  pub fn new(mars: Mars) -> Self {
+    Game {
+      pin_to_pid: HashMap::new(),
+      players: vec![],
+      eliminations: vec![],
+      mars,
+    }
+  }
+
   /// Add a player to the game with a pin
+  ///
+  /// # Errors
+  /// Fails if the pin is taken or the program is longer than the maximum
+  /// length of the `Mars`
   pub fn add_player_with_pin(
     &mut self,
     program: &[Instruction],
@@ -31,15 +74,64 @@ impl Game {
     pin: Pin,
   ) -> Result<Pin, GameError> {
     // if there is a pin conflict
-    if self.pin_to_pid.get(&pin).is_some() {
+    if self.pin_to_pid.contains_key(&pin) {
       Err(GameError::PinConflict { pin })
     } else {
-      let pid = self.mars.load_program(program, address);
+      let pspace = self.mars.new_pspace().borrow().clone();
+      let pid = self.mars.load_program(program, address)?;
       self.pin_to_pid.insert(pin, pid);
+      self.players.push(Player {
+        pin,
+        address,
+        warrior: Warrior {
+          instructions: program.to_vec(),
+          ..Warrior::default()
+        },
+        pspace,
+      });
       Ok(pin)
     }
   }
 
+  /// Add a player to the game with a pin, whose warrior keeps its private
+  /// storage in `pspace`
+  ///
+  /// # Errors
+  /// Fails if the pin is taken or the warrior is longer than the maximum
+  /// length of the `Mars`
+  pub fn add_warrior_with_pspace(
+    &mut self,
+    warrior: &Warrior,
+    address: Address,
+    pin: Pin,
+    pspace: PSpace,
+  ) -> Result<Pin, GameError> {
+    if self.pin_to_pid.contains_key(&pin) {
+      Err(GameError::PinConflict { pin })
+    } else {
+      let contents = pspace.borrow().clone();
+      let pid = self
+        .mars
+        .load_warrior_with_pspace(warrior, address, pspace)?;
+      self.pin_to_pid.insert(pin, pid);
+      self.players.push(Player {
+        pin,
+        address,
+        warrior: warrior.clone(),
+        pspace: contents,
+      });
+      Ok(pin)
+    }
+  }
+
+  /// Add a player to the game whose warrior begins executing at its start
+  /// offset
+  pub fn add_warrior(&mut self, warrior: &Warrior, address: Address) -> Result<Pin, GameError> {
+    let pin = self.gen_next_pin();
+    let pspace = self.mars.new_pspace();
+    self.add_warrior_with_pspace(warrior, address, pin, pspace)
+  }
+
   /// Add a player to the game
   pub fn add_player(
     &mut self,
@@ -60,8 +152,7 @@ impl Game {
   where
     R: Rng,
   {
-    // TODO: add "minimum margin" between programs
-    let load_addr = rng.gen();
+    let load_addr = self.random_address(program.len(), rng)?;
     self.add_player_with_pin(program, load_addr, pin)
   }
 
@@ -74,22 +165,133 @@ impl Game {
   where
     R: Rng,
   {
-    // TODO: add "minimum margin" between programs
-    let load_addr = rng.gen();
+    let load_addr = self.random_address(program.len(), rng)?;
     self.add_player(program, load_addr)
   }
 
+  /// Pick a load address for a program of `length` instructions the way pMARS
+  /// does. The first program is loaded at 0, later ones uniformly at random
+  /// among the addresses that are at least the Mars' minimum distance away
+  /// from every other program and that don't overlap them
+  pub fn random_address<R>(&self, length: usize, rng: &mut R) -> Result<Address, GameError>
+  where
+    R: Rng,
+  {
+    if self.players.is_empty() {
+      return Ok(0);
+    }
+
+    let size = self.mars.size();
+    let min_distance = self.mars.min_distance();
+    let candidates: Vec<usize> = (0..size)
+      .filter(|&candidate| {
+        self.players.iter().all(|player| {
+          let address = player.address as usize % size;
+          let other_length = player.warrior.instructions.len();
+          // distances from the other program to the candidate and back
+          let after = (candidate + size - address) % size;
+          let before = (address + size - candidate) % size;
+          after >= min_distance.max(other_length) && before >= min_distance.max(length)
+        })
+      })
+      .collect();
+
+    if candidates.is_empty() {
+      Err(GameError::NoRoom { length })
+    } else {
+      Ok(candidates[rng.gen_range(0, candidates.len())] as Address)
+    }
+  }
+
   /// Step the game forward one turn and return `Some(pin)` if the player with
-  /// the `pin` as a pin was eliminated. Otherwise `None`
+  /// the `pin` as a pin was eliminated. Otherwise `None`, which includes
+  /// stepping a game that has no players left
   pub fn step(&mut self) -> Option<Pin> {
-    self.mars.step().and_then(|ref pid| {
-      // NOTE: is this unwrap ok? I feel like it is
-      let pin = self
-        .pin_to_pid
-        .get(&pid)
-        .expect("Somehow executed with killed with process loaded without a pin");
-      Some(*pin)
-    })
+    self.step_with(&mut ())
+  }
+
+  /// Step the game forward one turn like `step`, telling `observer` what
+  /// happens, see `Mars::step_with`
+  pub fn step_with<O: Observer>(&mut self, observer: &mut O) -> Option<Pin> {
+    let pin = match self.mars.step_with(observer) {
+      Ok(ref outcome) if outcome.process_killed => Some(
+        self
+          .pin_of(outcome.pid)
+          .expect("Somehow executed with killed with process loaded without a pin"),
+      ),
+      _ => None,
+    };
+
+    if let Some(pin) = pin {
+      self.eliminations.push((pin, self.mars.cycle()));
+    }
+    pin
+  }
+
+  /// Undo the last step of a reversible `Mars`, see `Mars::step_back`
+  pub fn step_back(&mut self) -> Option<StepOutcome> {
+    let outcome = self.mars.step_back()?;
+    if outcome.process_killed {
+      self.eliminations.pop();
+    }
+    Some(outcome)
+  }
+
+  /// Run the game until a single player is left, or until the cycle limit is
+  /// reached. A game with a single player runs until the player is eliminated
+  pub fn run_to_completion(&mut self) -> BattleOutcome {
+    self.run_to_completion_with(&mut ())
+  }
+
+  /// Run the game like `run_to_completion`, telling `observer` what happens
+  /// in every step
+  pub fn run_to_completion_with<O: Observer>(&mut self, observer: &mut O) -> BattleOutcome {
+    while !self.is_finished() {
+      self.step_with(observer);
+    }
+    self.outcome()
+  }
+
+  fn outcome(&self) -> BattleOutcome {
+    let survivors = self.survivors();
+    match survivors.len() {
+      0 => BattleOutcome::AllDead,
+      1 if self.pin_to_pid.len() > 1 => BattleOutcome::Win(survivors[0]),
+      _ => BattleOutcome::Tie(survivors),
+    }
+  }
+
+  /// Return a replay of the game, with its result once the game is finished.
+  /// Changes made to memory through `mars_mut` aren't part of the replay
+  pub fn replay(&self) -> Replay {
+    let mut replay = Replay::new(self.mars.builder()).expect("the Mars was built by a builder");
+    for player in &self.players {
+      replay
+        .add_player(player.clone())
+        .expect("the player was loaded into the game");
+    }
+    if self.is_finished() {
+      replay.set_result(self.outcome(), self.mars.cycle());
+    }
+    replay
+  }
+
+  /// Return true once a single player is left, or none when only one was
+  /// added, or the cycle limit is reached
+  pub fn is_finished(&self) -> bool {
+    let last = if self.pin_to_pid.len() > 1 { 1 } else { 0 };
+    self.mars.process_count() <= last || self.mars.cycles_left() == 0
+  }
+
+  /// Return the pins of the players that haven't been eliminated, in order
+  pub fn survivors(&self) -> Vec<Pin> {
+    let mut pins: Vec<Pin> = self
+      .mars
+      .pids()
+      .filter_map(|pid| self.pin_of(pid))
+      .collect();
+    pins.sort();
+    pins
   }
 
   /// Return pins associated with their owned process id
@@ -99,16 +301,77 @@ impl Game {
 
   pub fn winner(&self) -> Option<Pin> {
     if self.mars.process_count() == 1 {
-      self.mars.pid()
+      self.mars.pid().and_then(|pid| self.pin_of(pid))
     } else {
       None
     }
   }
 
+  /// Return the eliminated players in the order they were eliminated, along
+  /// with the cycle they were eliminated in
+  pub fn eliminations(&self) -> &[(Pin, usize)] {
+    &self.eliminations
+  }
+
+  /// Return the place of every player from 1. Survivors share first place,
+  /// and eliminated players are placed after them, with the last player
+  /// eliminated placed highest
+  pub fn placings(&self) -> Vec<(Pin, usize)> {
+    let survivors = self.survivors();
+    let first_eliminated = survivors.len() + 1;
+    let mut placings: Vec<(Pin, usize)> = survivors.into_iter().map(|pin| (pin, 1)).collect();
+    placings.extend(
+      self
+        .eliminations
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &(pin, _))| (pin, first_eliminated + i)),
+    );
+    placings
+  }
+
+  /// Return the points of every player with the standard multi-warrior
+  /// scoring: with W players and S survivors, each survivor gets (W*W-1)/S
+  /// points and eliminated players get none. With two players this gives 3
+  /// points for a win and 1 for a tie
+  pub fn scores(&self) -> Vec<(Pin, usize)> {
+    let players = self.pin_to_pid.len();
+    let survivors = self.survivors();
+    let mut scores: Vec<(Pin, usize)> = self
+      .pin_to_pid
+      .keys()
+      .map(|&pin| {
+        if survivors.contains(&pin) {
+          (pin, (players * players - 1) / survivors.len())
+        } else {
+          (pin, 0)
+        }
+      })
+      .collect();
+    scores.sort();
+    scores
+  }
+
   pub fn mars(&self) -> &Mars {
     &self.mars
   }
 
+  /// Return the simulator for changes to its memory. Programs loaded through
+  /// it directly have no pin
+  pub fn mars_mut(&mut self) -> &mut Mars {
+    &mut self.mars
+  }
+
+  /// Return the pin of the player that owns the process
+  fn pin_of(&self, pid: Pid) -> Option<Pin> {
+    self
+      .pin_to_pid
+      .iter()
+      .find(|&(_, &owner)| owner == pid)
+      .map(|(&pin, _)| pin)
+  }
+
   /// Return the next available pin
   fn gen_next_pin(&mut self) -> Pin {
     let mut pin = 0;
@@ -127,6 +390,7 @@ impl Game {
 mod test {
   use super::*;
   use redcode::{AddressingMode::*, OpCode::*, OpMode::*, *};
+  use simulation::MarsBuilder;
 
   #[test]
   fn single_process_is_winner() {
@@ -139,4 +403,127 @@ mod test {
     game.add_player(program, 0).expect("should not conflict");
     assert_eq!(None, game.winner());
   }
+
+  #[test]
+  fn run_to_completion() {
+    let imp = &[Instruction::new(Mov, I, Direct, 0, Direct, 1)];
+    let dat = &[Instruction::default()];
+    let mars = || MarsBuilder::new(800).max_cycles(100).build().unwrap();
+
+    let mut game = Game::new(mars());
+    game.add_player_with_pin(imp, 0, 3).unwrap();
+    game.add_player_with_pin(imp, 400, 7).unwrap();
+    assert_eq!(game.run_to_completion(), BattleOutcome::Tie(vec![3, 7]));
+    assert_eq!(game.mars().cycle(), 200);
+
+    let mut game = Game::new(mars());
+    game.add_player_with_pin(dat, 0, 3).unwrap();
+    game.add_player_with_pin(imp, 400, 7).unwrap();
+    assert_eq!(game.run_to_completion(), BattleOutcome::Win(7));
+
+    let mut game = Game::new(mars());
+    game.add_player_with_pin(dat, 0, 3).unwrap();
+    assert_eq!(game.run_to_completion(), BattleOutcome::AllDead);
+  }
+
+  #[test]
+  fn step_back_restores_eliminations() {
+    let imp = &[Instruction::new(Mov, I, Direct, 0, Direct, 1)];
+    let dat = &[Instruction::default()];
+    let mars = MarsBuilder::new(800).reversible(true).build().unwrap();
+
+    let mut game = Game::new(mars);
+    game.add_player_with_pin(dat, 0, 3).unwrap();
+    game.add_player_with_pin(imp, 400, 7).unwrap();
+    assert_eq!(game.step(), Some(3));
+    assert_eq!(game.winner(), Some(7));
+
+    assert!(game.step_back().unwrap().process_killed);
+    assert!(game.eliminations().is_empty());
+    assert_eq!(game.survivors(), vec![3, 7]);
+    assert_eq!(game.step_back(), None);
+  }
+
+  #[test]
+  fn random_placement_keeps_distance() {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    let program = &[Instruction::new(Mov, I, Direct, 0, Direct, 1); 20];
+    let builder = MarsBuilder::new(800).max_length(20).min_distance(150);
+    let place = |seed| {
+      let mut rng = StdRng::seed_from_u64(seed);
+      let mut game = Game::new(builder.build().unwrap());
+      for _ in 0..4 {
+        game.add_player_rand(program, &mut rng).unwrap();
+      }
+      game
+        .players
+        .iter()
+        .map(|player| player.address)
+        .collect::<Vec<_>>()
+    };
+
+    for seed in 0..20 {
+      let addresses = place(seed);
+      assert_eq!(addresses[0], 0);
+      for (i, &a) in addresses.iter().enumerate() {
+        for &b in &addresses[i + 1..] {
+          let distance = (a as i64 - b as i64).abs();
+          assert!(distance.min(800 - distance) >= 150, "{:?}", addresses);
+        }
+      }
+      // the same seed places programs at the same addresses
+      assert_eq!(place(seed), addresses);
+    }
+  }
+
+  #[test]
+  fn random_placement_without_room() {
+    let mut rng = rand::thread_rng();
+    let program = &[Instruction::default(); 100];
+    let mut game = Game::new(MarsBuilder::new(400).min_distance(200).build().unwrap());
+    game.add_player_rand(program, &mut rng).unwrap();
+    game.add_player_rand(program, &mut rng).unwrap();
+    assert_eq!(
+      game.add_player_rand(program, &mut rng).unwrap_err(),
+      GameError::NoRoom { length: 100 }
+    );
+  }
+
+  #[test]
+  fn melee_scoring() {
+    let imp = &[Instruction::new(Mov, I, Direct, 0, Direct, 1)];
+    let dat = &[Instruction::default()];
+    // dies on its second turn
+    let slow_dat = &[
+      Instruction::new(Jmp, B, Direct, 1, Direct, 0),
+      Instruction::default(),
+    ];
+
+    let mut game = Game::new(MarsBuilder::new(800).max_cycles(50).build().unwrap());
+    game.add_player_with_pin(slow_dat, 0, 0).unwrap();
+    game.add_player_with_pin(imp, 200, 1).unwrap();
+    game.add_player_with_pin(dat, 400, 2).unwrap();
+    game.add_player_with_pin(imp, 600, 3).unwrap();
+
+    assert_eq!(game.run_to_completion(), BattleOutcome::Tie(vec![1, 3]));
+    assert_eq!(game.eliminations(), &[(2, 3), (0, 5)]);
+    assert_eq!(game.placings(), vec![(1, 1), (3, 1), (0, 3), (2, 4)]);
+    // (4 * 4 - 1) / 2 points for each of the two survivors
+    assert_eq!(game.scores(), vec![(0, 0), (1, 7), (2, 0), (3, 7)]);
+  }
+
+  #[test]
+  fn two_player_scores() {
+    let imp = &[Instruction::new(Mov, I, Direct, 0, Direct, 1)];
+    let mut game = Game::new(MarsBuilder::new(800).max_cycles(50).build().unwrap());
+    game.add_player_with_pin(imp, 0, 4).unwrap();
+    game
+      .add_player_with_pin(&[Instruction::default()], 400, 5)
+      .unwrap();
+    game.run_to_completion();
+
+    assert_eq!(game.winner(), Some(4));
+    assert_eq!(game.scores(), vec![(4, 3), (5, 0)]);
+  }
 }
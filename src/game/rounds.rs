@@ -0,0 +1,443 @@
+//! Battles of several rounds
+use {
+  super::{BattleOutcome, Game, GameError, Pin},
+  rand::{rngs::StdRng, SeedableRng},
+  redcode::{Address, Warrior},
+  replay::Replay,
+  simulation::{Mars, MarsBuilder, MarsError, PSpace},
+  std::{cell::RefCell, rc::Rc},
+};
+
+/// A player's results over the rounds of a match
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Record {
+  pub wins: usize,
+  pub losses: usize,
+  pub ties: usize,
+}
+
+impl Record {
+  /// Return the score with the standard 3 points for a win and 1 for a tie
+  pub fn points(&self) -> usize {
+    3 * self.wins + self.ties
+  }
+}
+
+impl ::std::ops::AddAssign for Record {
+  fn add_assign(&mut self, other: Record) {
+    self.wins += other.wins;
+    self.losses += other.losses;
+    self.ties += other.ties;
+  }
+}
+
+#[derive(Debug)]
+struct Player {
+  pin: Pin,
+  warrior: Warrior,
+  /// Load address, or `None` to be placed at random every round
+  address: Option<Address>,
+  /// Private storage, which outlives the rounds
+  pspace: PSpace,
+  record: Record,
+  /// Multi-warrior score summed over the rounds, see `Game::scores`
+  score: usize,
+}
+
+/// A clone gets its own copy of the P-space
+impl Clone for Player {
+  fn clone(&self) -> Self {
+    Player {
+      warrior: self.warrior.clone(),
+      pspace: Rc::new(RefCell::new(self.pspace.borrow().clone())),
+      ..*self
+    }
+  }
+}
+
+/// A battle of several rounds, each fought in a fresh core
+///
+/// Players keep their P-space from round to round. As ICWS'94 specifies,
+/// cell 0 holds the result of the previous round: 0 if the player was
+/// eliminated, otherwise the number of players that survived
+#[derive(Debug, Clone)]
+pub struct Match {
+  builder: MarsBuilder,
+  players: Vec<Player>,
+  rounds: usize,
+  /// Number of rounds played so far
+  round: usize,
+  /// Seed of `rng`, recorded in replays
+  seed: u64,
+  /// Source of random load addresses
+  rng: StdRng,
+  /// Load address of every player in the last round
+  placements: Vec<(Pin, Address)>,
+  /// The game of the last round, to replay it
+  last_game: Option<Game>,
+}
+
+impl Match {
+  /// Create a match of `rounds` rounds, each fought in a `Mars` built by
+  /// `builder`
+  pub fn new(builder: MarsBuilder, rounds: usize) -> Result<Self, MarsError> {
+    Match::with_seed(builder, rounds, ::rand::random())
+  }
+
+  /// Create a match whose players are placed at the same random addresses
+  /// whenever it is created with the same `seed`
+  pub fn with_seed(builder: MarsBuilder, rounds: usize, seed: u64) -> Result<Self, MarsError> {
+    builder.build()?;
+
+    Ok(Match {
+      builder,
+      players: vec![],
+      rounds,
+      round: 0,
+      seed,
+      rng: StdRng::seed_from_u64(seed),
+      placements: vec![],
+      last_game: None,
+    })
+  }
+
+  /// Add a player with a pin, whose warrior is loaded at `address` every
+  /// round
+  pub fn add_player_with_pin(
+    &mut self,
+    warrior: Warrior,
+    address: Address,
+    pin: Pin,
+  ) -> Result<Pin, GameError> {
+    self.add(warrior, Some(address), pin)
+  }
+
+  /// Add a player, whose warrior is loaded at `address` every round
+  pub fn add_player(&mut self, warrior: Warrior, address: Address) -> Result<Pin, GameError> {
+    let pin = self.gen_next_pin();
+    self.add(warrior, Some(address), pin)
+  }
+
+  /// Add a player with a pin, whose warrior is loaded at a new random address
+  /// every round. See `Game::random_address`
+  pub fn add_player_with_pin_rand(&mut self, warrior: Warrior, pin: Pin) -> Result<Pin, GameError> {
+    self.add(warrior, None, pin)
+  }
+
+  /// Add a player, whose warrior is loaded at a new random address every
+  /// round. See `Game::random_address`
+  pub fn add_player_rand(&mut self, warrior: Warrior) -> Result<Pin, GameError> {
+    let pin = self.gen_next_pin();
+    self.add(warrior, None, pin)
+  }
+
+  /// Add a player, failing if the pin is taken or the warrior is longer
+  /// than the maximum length
+  fn add(
+    &mut self,
+    warrior: Warrior,
+    address: Option<Address>,
+    pin: Pin,
+  ) -> Result<Pin, GameError> {
+    if self.players.iter().any(|player| player.pin == pin) {
+      return Err(GameError::PinConflict { pin });
+    }
+    let mars = self.build_mars();
+    if warrior.instructions.len() > mars.max_length() {
+      return Err(GameError::Mars(MarsError::ProgramTooLong {
+        length: warrior.instructions.len(),
+        max_length: mars.max_length(),
+      }));
+    }
+
+    let pspace = mars.new_pspace();
+    self.players.push(Player {
+      pin,
+      warrior,
+      address,
+      pspace,
+      record: Record::default(),
+      score: 0,
+    });
+    Ok(pin)
+  }
+
+  /// Fight the next round in a fresh core and record its result
+  ///
+  /// # Errors
+  /// Fails if there is no room for a player that is placed at random
+  pub fn run_round(&mut self) -> Result<BattleOutcome, GameError> {
+    let mut game = Game::new(self.build_mars());
+    self.placements.clear();
+    for player in &self.players {
+      let address = match player.address {
+        Some(address) => address,
+        None => game.random_address(player.warrior.instructions.len(), &mut self.rng)?,
+      };
+      game.add_warrior_with_pspace(&player.warrior, address, player.pin, player.pspace.clone())?;
+      self.placements.push((player.pin, address));
+    }
+
+    let outcome = game.run_to_completion();
+    let survivors = game.survivors();
+    let scores = game.scores();
+
+    for player in &mut self.players {
+      let result = if survivors.contains(&player.pin) {
+        survivors.len()
+      } else {
+        0
+      };
+      player.pspace.borrow_mut()[0] = result as Address;
+      player.score += scores
+        .iter()
+        .find(|&&(pin, _)| pin == player.pin)
+        .map_or(0, |&(_, score)| score);
+
+      match outcome {
+        BattleOutcome::Win(pin) if pin == player.pin => player.record.wins += 1,
+        BattleOutcome::Tie(ref pins) if pins.contains(&player.pin) => player.record.ties += 1,
+        _ => player.record.losses += 1,
+      }
+    }
+
+    self.round += 1;
+    self.last_game = Some(game);
+    Ok(outcome)
+  }
+
+  /// Fight all of the remaining rounds
+  pub fn run(&mut self) -> Result<(), GameError> {
+    while !self.is_finished() {
+      self.run_round()?;
+    }
+    Ok(())
+  }
+
+  /// Return true once every round has been fought
+  pub fn is_finished(&self) -> bool {
+    self.round >= self.rounds
+  }
+
+  /// Return the number of rounds fought so far
+  pub fn round(&self) -> usize {
+    self.round
+  }
+
+  /// Return the number of rounds in the match
+  pub fn rounds(&self) -> usize {
+    self.rounds
+  }
+
+  /// Return the record of the player with the pin
+  pub fn record(&self, pin: Pin) -> Option<Record> {
+    self
+      .players
+      .iter()
+      .find(|player| player.pin == pin)
+      .map(|player| player.record)
+  }
+
+  /// Return the multi-warrior score of the player with the pin, summed over
+  /// the rounds fought so far
+  pub fn score(&self, pin: Pin) -> Option<usize> {
+    self
+      .players
+      .iter()
+      .find(|player| player.pin == pin)
+      .map(|player| player.score)
+  }
+
+  /// Return the record of every player zipped with their pin
+  pub fn records(&self) -> impl Iterator<Item = (Pin, Record)> + '_ {
+    self
+      .players
+      .iter()
+      .map(|player| (player.pin, player.record))
+  }
+
+  /// Return the load address of every player in the last round, zipped with
+  /// their pin
+  pub fn placements(&self) -> &[(Pin, Address)] {
+    &self.placements
+  }
+
+  /// Return a replay of the last round, along with the seed of the match, or
+  /// `None` if no round was fought
+  pub fn replay(&self) -> Option<Replay> {
+    self.last_game.as_ref().map(|game| {
+      let mut replay = game.replay();
+      replay.set_seed(self.seed);
+      replay
+    })
+  }
+
+  /// Return the private storage of the player with the pin
+  pub fn pspace(&self, pin: Pin) -> Option<&PSpace> {
+    self
+      .players
+      .iter()
+      .find(|player| player.pin == pin)
+      .map(|player| &player.pspace)
+  }
+
+  /// Return the next available pin
+  fn gen_next_pin(&self) -> Pin {
+    (0..)
+      .find(|&pin| self.players.iter().all(|player| player.pin != pin))
+      .expect("ran out of pins")
+  }
+
+  fn build_mars(&self) -> Mars {
+    self
+      .builder
+      .build()
+      .expect("the builder was validated when the match was created")
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use redcode::{AddressingMode::*, OpCode::*, OpMode::*, *};
+
+  fn warrior(instructions: Vec<Instruction>) -> Warrior {
+    Warrior {
+      instructions,
+      ..Warrior::default()
+    }
+  }
+
+  #[test]
+  fn records_are_kept_per_pin() {
+    let imp = warrior(vec![Instruction::new(Mov, I, Direct, 0, Direct, 1)]);
+    let dat = warrior(vec![Instruction::default()]);
+
+    let builder = MarsBuilder::new(800).max_cycles(100);
+    let mut battle = Match::new(builder, 3).unwrap();
+    battle.add_player_with_pin(imp, 0, 5).unwrap();
+    battle.add_player_with_pin(dat, 400, 9).unwrap();
+    battle.run().unwrap();
+
+    assert!(battle.is_finished());
+    assert_eq!(battle.round(), 3);
+    assert_eq!(
+      battle.record(5),
+      Some(Record {
+        wins: 3,
+        losses: 0,
+        ties: 0
+      })
+    );
+    assert_eq!(battle.score(5), Some(9));
+    assert_eq!(
+      battle.record(9),
+      Some(Record {
+        wins: 0,
+        losses: 3,
+        ties: 0
+      })
+    );
+  }
+
+  #[test]
+  fn previous_result_is_in_p_space() {
+    // copy the result of the previous round to P-space cell 1, then loop
+    let recorder = warrior(vec![
+      Instruction::new(Ldp, AB, Immediate, 0, Direct, 2),
+      Instruction::new(Stp, B, Direct, 1, Immediate, 1),
+      Instruction::new(Jmp, B, Direct, 0, Direct, 0),
+    ]);
+    // dies after the recorder has stored the result
+    let slow_dat = warrior(vec![
+      Instruction::new(Jmp, B, Direct, 1, Direct, 0),
+      Instruction::new(Jmp, B, Direct, 1, Direct, 0),
+      Instruction::default(),
+    ]);
+
+    let mut battle = Match::new(MarsBuilder::new(800).max_cycles(100), 2).unwrap();
+    let pin = battle.add_player(recorder, 0).unwrap();
+    battle.add_player(slow_dat, 400).unwrap();
+
+    battle.run_round().unwrap();
+    // there was no previous round
+    assert_eq!(battle.pspace(pin).unwrap().borrow()[1], 799);
+    assert_eq!(battle.pspace(pin).unwrap().borrow()[0], 1);
+
+    // a clone fights its rounds with its own P-space
+    let mut clone = battle.clone();
+    clone.run_round().unwrap();
+    assert_eq!(clone.pspace(pin).unwrap().borrow()[1], 1);
+    assert_eq!(battle.pspace(pin).unwrap().borrow()[1], 799);
+
+    battle.run_round().unwrap();
+    assert_eq!(battle.pspace(pin).unwrap().borrow()[1], 1);
+  }
+
+  #[test]
+  fn last_round_is_replayed() {
+    let imp = warrior(vec![Instruction::new(Mov, I, Direct, 0, Direct, 1)]);
+    let dat = warrior(vec![Instruction::default()]);
+    let mut battle = Match::with_seed(MarsBuilder::new(800).max_cycles(100), 2, 11).unwrap();
+    assert!(battle.replay().is_none());
+    let pin = battle.add_player_rand(imp).unwrap();
+    battle.add_player_rand(dat).unwrap();
+
+    battle.run().unwrap();
+    let mut replay = battle.replay().unwrap();
+    assert_eq!(
+      replay.result().map(|(outcome, _)| outcome),
+      Some(&BattleOutcome::Win(pin))
+    );
+    let placements: Vec<_> = replay
+      .players()
+      .iter()
+      .map(|player| (player.pin, player.address))
+      .collect();
+    assert_eq!(placements, battle.placements());
+    assert_eq!(replay.seed(), Some(11));
+    // P-space is saved as it was when the round started
+    assert_eq!(replay.players()[0].pspace[0], 1);
+    assert_eq!(replay.run().unwrap(), BattleOutcome::Win(pin));
+  }
+
+  #[test]
+  fn long_warriors_are_rejected() {
+    let builder = MarsBuilder::new(800).max_length(10).min_distance(10);
+    let mut battle = Match::new(builder, 1).unwrap();
+    let long = warrior(vec![Instruction::default(); 11]);
+    assert_eq!(
+      battle.add_player_rand(long),
+      Err(GameError::Mars(MarsError::ProgramTooLong {
+        length: 11,
+        max_length: 10,
+      }))
+    );
+  }
+
+  #[test]
+  fn random_placement_is_seeded() {
+    let imp = warrior(vec![Instruction::new(Mov, I, Direct, 0, Direct, 1)]);
+    let placements = |seed| {
+      let mut battle = Match::with_seed(MarsBuilder::new(800).max_cycles(50), 5, seed).unwrap();
+      battle.add_player_rand(imp.clone()).unwrap();
+      battle.add_player_rand(imp.clone()).unwrap();
+      (0..5)
+        .map(|_| {
+          battle.run_round().unwrap();
+          battle.placements().to_vec()
+        })
+        .collect::<Vec<_>>()
+    };
+
+    let rounds = placements(7);
+    assert_eq!(rounds, placements(7));
+    assert_ne!(rounds, placements(8));
+    for round in &rounds {
+      assert_eq!(round.len(), 2);
+      assert_eq!((round[0].0, round[1].0), (0, 1));
+    }
+    // players are placed anew every round
+    assert!(rounds.iter().any(|round| *round != rounds[0]));
+  }
+}
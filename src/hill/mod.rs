@@ -0,0 +1,563 @@
+//! King of the hill, where challengers battle every warrior on a hill of a
+//! fixed size and the warrior with the lowest score is pushed off
+//!
+//! A hill is kept in a directory, with the source of each warrior in
+//! `<id>.red` and everything else in `hill.txt`
+use {
+  failure::Fail,
+  game::{GameError, Match, Record},
+  parse::{parse_program_with_environment, Environment, ParseError},
+  rand::{rngs::StdRng, Rng, SeedableRng},
+  redcode::Warrior,
+  simulation::{MarsBuilder, MarsError},
+  std::{
+    cmp::Reverse,
+    collections::HashMap,
+    fmt::Write,
+    fs, io,
+    path::{Path, PathBuf},
+  },
+};
+
+const STATE_FILE: &str = "hill.txt";
+
+#[derive(Debug, Fail)]
+pub enum HillError {
+  #[fail(display = "failed to access {}: {}", path, cause)]
+  Io {
+    path: String,
+    #[cause]
+    cause: io::Error,
+  },
+  #[fail(display = "failed to parse challenger: {}", _0)]
+  Parse(Box<ParseError>),
+  #[fail(display = "invalid hill state on line {} of {}", line, path)]
+  Corrupt { path: String, line: usize },
+  #[fail(display = "a hill must have room for at least one member")]
+  ZeroSize,
+  #[fail(display = "{}", _0)]
+  Mars(#[cause] MarsError),
+  #[fail(display = "{}", _0)]
+  Game(#[cause] GameError),
+}
+
+impl From<MarsError> for HillError {
+  fn from(error: MarsError) -> Self {
+    HillError::Mars(error)
+  }
+}
+
+impl From<GameError> for HillError {
+  fn from(error: GameError) -> Self {
+    HillError::Game(error)
+  }
+}
+
+/// A warrior on the hill
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+  /// Identifier that is unique over the lifetime of the hill
+  pub id: usize,
+  pub source: String,
+  pub warrior: Warrior,
+  /// Number of challenges the warrior has survived
+  pub age: usize,
+}
+
+impl Member {
+  /// Return the warrior's name, or its id if it doesn't have one
+  pub fn name(&self) -> String {
+    self
+      .warrior
+      .name
+      .clone()
+      .unwrap_or_else(|| format!("#{}", self.id))
+  }
+}
+
+/// A member's results against the rest of the hill
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Standing {
+  pub id: usize,
+  pub record: Record,
+}
+
+/// The result of a challenge
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Challenge {
+  /// Id given to the challenger
+  pub id: usize,
+  /// Rank of the challenger from 1, or `None` if it was pushed off
+  pub rank: Option<usize>,
+  /// The member pushed off of the hill to make room, which may be the
+  /// challenger
+  pub pushed_off: Option<Member>,
+}
+
+/// A king of the hill
+#[derive(Debug, Clone)]
+pub struct Hill {
+  dir: PathBuf,
+  builder: MarsBuilder,
+  /// Maximum number of members
+  size: usize,
+  /// Rounds fought between each pair of members
+  rounds: usize,
+  /// Seed of the next challenge
+  seed: u64,
+  next_id: usize,
+  members: Vec<Member>,
+  /// Record of the first member against the second
+  results: HashMap<(usize, usize), Record>,
+}
+
+impl Hill {
+  /// Create an empty hill in `dir`, whose battles are fought in a `Mars`
+  /// built by `builder`
+  ///
+  /// # Errors
+  /// Fails if `size` is 0, the builder is invalid or the directory can't be
+  /// written
+  pub fn create(
+    dir: &Path,
+    builder: MarsBuilder,
+    size: usize,
+    rounds: usize,
+    seed: u64,
+  ) -> Result<Self, HillError> {
+    if size == 0 {
+      return Err(HillError::ZeroSize);
+    }
+    builder.build()?;
+    fs::create_dir_all(dir).map_err(io_error(dir))?;
+
+    let hill = Hill {
+      dir: dir.to_path_buf(),
+      builder,
+      size,
+      rounds,
+      seed,
+      next_id: 0,
+      members: vec![],
+      results: HashMap::new(),
+    };
+    hill.save()?;
+    Ok(hill)
+  }
+
+  /// Open a hill previously created in `dir`, whose battles are fought in a
+  /// `Mars` with the settings it was created with
+  pub fn open(dir: &Path) -> Result<Self, HillError> {
+    let path = dir.join(STATE_FILE);
+    let state = fs::read_to_string(&path).map_err(io_error(&path))?;
+    let corrupt = |line| HillError::Corrupt {
+      path: path.display().to_string(),
+      line,
+    };
+
+    let mut core_size = None;
+    // the hill's size and the line it is on
+    let mut size = None;
+    // settings other than the core size, applied once it is known
+    let mut settings = vec![];
+    let mut hill = Hill {
+      dir: dir.to_path_buf(),
+      builder: MarsBuilder::new(0),
+      size: 0,
+      rounds: 0,
+      seed: 0,
+      next_id: 0,
+      members: vec![],
+      results: HashMap::new(),
+    };
+    // id and age of every member, read once the builder is known
+    let mut members = vec![];
+
+    for (i, line) in state.lines().enumerate() {
+      let words: Vec<&str> = line.split_whitespace().collect();
+      if words.is_empty() {
+        continue;
+      }
+      if let ["mars", setting, value] = words.as_slice() {
+        let value = value.parse::<usize>().map_err(|_| corrupt(i + 1))?;
+        match *setting {
+          "size" => core_size = Some(value),
+          _ => settings.push((*setting, value, i + 1)),
+        }
+        continue;
+      }
+      let numbers = words[1..]
+        .iter()
+        .map(|word| word.parse::<u64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| corrupt(i + 1))?;
+
+      match (words[0], numbers.as_slice()) {
+        ("size", &[value]) => size = Some((value as usize, i + 1)),
+        ("rounds", &[rounds]) => hill.rounds = rounds as usize,
+        ("seed", &[seed]) => hill.seed = seed,
+        ("next", &[next_id]) => hill.next_id = next_id as usize,
+        ("member", &[id, age]) => members.push((id as usize, age as usize)),
+        ("result", &[first, second, wins, losses, ties]) => {
+          let record = Record {
+            wins: wins as usize,
+            losses: losses as usize,
+            ties: ties as usize,
+          };
+          hill
+            .results
+            .insert((first as usize, second as usize), record);
+        }
+        _ => return Err(corrupt(i + 1)),
+      }
+    }
+
+    let end = state.lines().count();
+    hill.size = match size {
+      Some((0, line)) => return Err(corrupt(line)),
+      Some((size, _)) => size,
+      None => return Err(corrupt(end)),
+    };
+    let mut builder = MarsBuilder::new(core_size.ok_or_else(|| corrupt(end))?);
+    for (setting, value, line) in settings {
+      builder = builder
+        .setting(setting, value)
+        .ok_or_else(|| corrupt(line))?;
+    }
+    builder.build()?;
+    hill.builder = builder;
+
+    for (id, age) in members {
+      let source =
+        fs::read_to_string(hill.source_path(id)).map_err(io_error(&hill.source_path(id)))?;
+      let warrior = hill.parse(&source)?;
+      hill.members.push(Member {
+        id,
+        source,
+        warrior,
+        age,
+      });
+    }
+
+    Ok(hill)
+  }
+
+  /// Write the hill to its directory
+  pub fn save(&self) -> Result<(), HillError> {
+    let mut state = String::new();
+    for &(setting, value) in &self.builder.settings() {
+      let _ = writeln!(state, "mars {} {}", setting, value);
+    }
+    let _ = writeln!(state, "size {}", self.size);
+    let _ = writeln!(state, "rounds {}", self.rounds);
+    let _ = writeln!(state, "seed {}", self.seed);
+    let _ = writeln!(state, "next {}", self.next_id);
+    for member in &self.members {
+      let _ = writeln!(state, "member {} {}", member.id, member.age);
+    }
+
+    let mut results: Vec<_> = self.results.iter().collect();
+    results.sort_by_key(|&(&pair, _)| pair);
+    for (&(first, second), record) in results {
+      let _ = writeln!(
+        state,
+        "result {} {} {} {} {}",
+        first, second, record.wins, record.losses, record.ties
+      );
+    }
+
+    let path = self.dir.join(STATE_FILE);
+    fs::write(&path, state).map_err(io_error(&path))
+  }
+
+  /// Battle a challenger against every member, then push the member with the
+  /// lowest score off of the hill if it is over its size. Members that stay
+  /// on the hill grow older, and the hill is saved
+  ///
+  /// # Errors
+  /// Fails if the challenger can't be parsed or a battle can't be fought, in
+  /// which case the hill is left as it was
+  pub fn challenge(&mut self, source: &str) -> Result<Challenge, HillError> {
+    let challenger = Member {
+      id: self.next_id,
+      source: source.to_string(),
+      warrior: self.parse(source)?,
+      age: 0,
+    };
+
+    // results are only recorded once every battle has been fought
+    let mut results = HashMap::new();
+    let mut rng = StdRng::seed_from_u64(self.seed);
+    for member in &self.members {
+      let mut battle = Match::with_seed(self.builder, self.rounds, rng.gen())?;
+      let challenger_pin = battle.add_player_rand(challenger.warrior.clone())?;
+      let member_pin = battle.add_player_rand(member.warrior.clone())?;
+      battle.run()?;
+
+      let record = battle.record(challenger_pin).expect("the player was added");
+      let member_record = battle.record(member_pin).expect("the player was added");
+      results.insert((challenger.id, member.id), record);
+      results.insert((member.id, challenger.id), member_record);
+    }
+
+    fs::write(self.source_path(challenger.id), source)
+      .map_err(io_error(&self.source_path(challenger.id)))?;
+    self.results.extend(results);
+    self.seed = rng.gen();
+    self.next_id += 1;
+    self.members.push(challenger);
+
+    let pushed_off = if self.members.len() > self.size {
+      // the newest of the members with the lowest score is pushed off
+      let loser = self
+        .standings()
+        .iter()
+        .min_by_key(|standing| (standing.record.points(), Reverse(standing.id)))
+        .map(|standing| standing.id)
+        .expect("the hill has members");
+      let index = self
+        .members
+        .iter()
+        .position(|member| member.id == loser)
+        .expect("standings only contain members");
+
+      let member = self.members.remove(index);
+      self
+        .results
+        .retain(|&(first, second), _| first != loser && second != loser);
+      let path = self.source_path(loser);
+      fs::remove_file(&path).map_err(io_error(&path))?;
+      Some(member)
+    } else {
+      None
+    };
+
+    let id = self.next_id - 1;
+    for member in &mut self.members {
+      if member.id != id {
+        member.age += 1;
+      }
+    }
+
+    let rank = self
+      .standings()
+      .iter()
+      .position(|standing| standing.id == id)
+      .map(|index| index + 1);
+    self.save()?;
+
+    Ok(Challenge {
+      id,
+      rank,
+      pushed_off,
+    })
+  }
+
+  /// Return the members in the order they joined the hill
+  pub fn members(&self) -> &[Member] {
+    &self.members
+  }
+
+  /// Return the member with the id
+  pub fn member(&self, id: usize) -> Option<&Member> {
+    self.members.iter().find(|member| member.id == id)
+  }
+
+  /// Return every member's results against the rest of the hill, from the
+  /// most points to the least
+  pub fn standings(&self) -> Vec<Standing> {
+    let mut standings: Vec<Standing> = self
+      .members
+      .iter()
+      .map(|member| {
+        let mut record = Record::default();
+        for other in &self.members {
+          if let Some(&result) = self.results.get(&(member.id, other.id)) {
+            record += result;
+          }
+        }
+        Standing {
+          id: member.id,
+          record,
+        }
+      })
+      .collect();
+    standings.sort_by_key(|standing| Reverse(standing.record.points()));
+    standings
+  }
+
+  pub fn size(&self) -> usize {
+    self.size
+  }
+
+  pub fn rounds(&self) -> usize {
+    self.rounds
+  }
+
+  /// Return the configuration of the `Mars` battles are fought in
+  pub fn builder(&self) -> MarsBuilder {
+    self.builder
+  }
+
+  fn parse(&self, source: &str) -> Result<Warrior, HillError> {
+    let env = Environment {
+      rounds: self.rounds,
+      ..Environment::from(&self.builder)
+    };
+    let warrior =
+      parse_program_with_environment(source, &env).map_err(|e| HillError::Parse(Box::new(e)))?;
+    // the hill may have no members to fight, which would load the warrior
+    if warrior.instructions.len() > env.max_length {
+      return Err(HillError::Mars(MarsError::ProgramTooLong {
+        length: warrior.instructions.len(),
+        max_length: env.max_length,
+      }));
+    }
+    Ok(warrior)
+  }
+
+  fn source_path(&self, id: usize) -> PathBuf {
+    self.dir.join(format!("{}.red", id))
+  }
+}
+
+fn io_error(path: &Path) -> impl FnOnce(io::Error) -> HillError {
+  let path = path.display().to_string();
+  move |cause| HillError::Io { path, cause }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::env;
+
+  const IMP: &str = ";name Imp\nMOV.I 0, 1";
+  const DWARF: &str = ";name Dwarf\nADD #4, 3\nMOV 2, @2\nJMP -2\nDAT #0, #0";
+  const SUICIDE: &str = ";name Suicide\nDAT 0, 0";
+
+  fn hill_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("libcw-hill-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+  }
+
+  fn names(hill: &Hill) -> Vec<String> {
+    hill
+      .standings()
+      .iter()
+      .map(|standing| hill.member(standing.id).unwrap().name())
+      .collect()
+  }
+
+  #[test]
+  fn lowest_scorer_is_pushed_off() {
+    let dir = hill_dir("push");
+    let builder = MarsBuilder::new(800).max_cycles(200);
+    let mut hill = Hill::create(&dir, builder, 2, 4, 1).unwrap();
+
+    assert_eq!(hill.challenge(IMP).unwrap().rank, Some(1));
+    let challenge = hill.challenge(SUICIDE).unwrap();
+    assert_eq!(challenge.rank, Some(2));
+    assert_eq!(challenge.pushed_off, None);
+
+    let challenge = hill.challenge(DWARF).unwrap();
+    assert_eq!(challenge.pushed_off.unwrap().name(), "Suicide");
+    assert!(!dir.join("1.red").exists());
+    assert_eq!(names(&hill).len(), 2);
+    assert!(names(&hill).contains(&"Dwarf".to_string()));
+
+    // a challenger that can't make it pushes itself off
+    let challenge = hill.challenge(SUICIDE).unwrap();
+    assert_eq!(challenge.rank, None);
+    assert_eq!(challenge.pushed_off.unwrap().id, 3);
+
+    let ages: Vec<_> = hill
+      .members()
+      .iter()
+      .map(|member| (member.id, member.age))
+      .collect();
+    assert_eq!(ages, vec![(0, 3), (2, 1)]);
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn long_challengers_are_rejected() {
+    let dir = hill_dir("long");
+    let builder = MarsBuilder::new(800).max_length(2).min_distance(2);
+    let mut hill = Hill::create(&dir, builder, 2, 1, 1).unwrap();
+
+    match hill.challenge(DWARF) {
+      Err(HillError::Mars(MarsError::ProgramTooLong { length: 4, .. })) => {}
+      other => panic!("expected the challenger to be too long, got {:?}", other),
+    }
+    assert!(hill.members().is_empty());
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn failed_challenges_change_nothing() {
+    let dir = hill_dir("failed");
+    let builder = MarsBuilder::new(800).max_cycles(200);
+    let mut hill = Hill::create(&dir, builder, 3, 1, 1).unwrap();
+    hill.challenge(IMP).unwrap();
+    let standings = hill.standings();
+
+    // the battles are fought but the challenger can't be saved
+    fs::remove_dir_all(&dir).unwrap();
+    match hill.challenge(DWARF) {
+      Err(HillError::Io { .. }) => {}
+      other => panic!("expected the challenger not to be saved, got {:?}", other),
+    }
+    assert_eq!(hill.standings(), standings);
+    assert!(hill.results.is_empty());
+
+    fs::create_dir_all(&dir).unwrap();
+    assert_eq!(hill.challenge(DWARF).unwrap().id, 1);
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn hill_is_persisted() {
+    let dir = hill_dir("persist");
+    let builder = MarsBuilder::new(800).max_cycles(200);
+    let mut hill = Hill::create(&dir, builder, 3, 2, 7).unwrap();
+    hill.challenge(IMP).unwrap();
+    hill.challenge(DWARF).unwrap();
+
+    let opened = Hill::open(&dir).unwrap();
+    assert_eq!(opened.members(), hill.members());
+    assert_eq!(opened.standings(), hill.standings());
+    assert_eq!(opened.size(), 3);
+    assert_eq!(opened.rounds(), 2);
+    assert_eq!(opened.builder(), builder);
+
+    // both copies continue from the same seed
+    hill.challenge(SUICIDE).unwrap();
+    let mut opened = opened;
+    opened.challenge(SUICIDE).unwrap();
+    assert_eq!(opened.standings(), hill.standings());
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn hill_size_is_checked() {
+    let dir = hill_dir("size");
+    let builder = MarsBuilder::new(800);
+    assert!(matches!(
+      Hill::create(&dir, builder, 0, 1, 1),
+      Err(HillError::ZeroSize)
+    ));
+
+    Hill::create(&dir, builder, 3, 1, 1).unwrap();
+    let path = dir.join(STATE_FILE);
+    let state = fs::read_to_string(&path).unwrap();
+    fs::write(&path, state.replace("size 3\n", "size 0\n")).unwrap();
+    assert!(matches!(
+      Hill::open(&dir),
+      Err(HillError::Corrupt { line: 9, .. })
+    ));
+    fs::write(&path, state.replace("size 3\n", "")).unwrap();
+    assert!(matches!(Hill::open(&dir), Err(HillError::Corrupt { .. })));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
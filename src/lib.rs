@@ -1,11 +1,16 @@
+// `failure_derive` generates its impls inside of an anonymous const
+#![allow(non_local_definitions)]
 extern crate failure;
 extern crate itertools;
 extern crate nom;
 extern crate rand;
 pub mod game;
+pub mod hill;
 pub mod parse;
 pub mod redcode;
+pub mod replay;
 pub mod simulation;
+pub mod tournament;
 pub use self::parse::*;
 pub use self::redcode::*;
 pub use self::simulation::*;
@@ -0,0 +1,237 @@
+//! Errors reported while parsing a program
+use {super::expr::EvalError, failure::Fail, std::fmt};
+
+/// What went wrong while parsing a program
+#[derive(Debug, Clone, PartialEq, Eq, Fail)]
+pub enum ParseErrorKind {
+  #[fail(display = "syntax error")]
+  Syntax,
+  #[fail(display = "undefined label `{}`", _0)]
+  UndefinedLabel(String),
+  #[fail(display = "duplicate label `{}`", _0)]
+  DuplicateLabel(String),
+  #[fail(display = "division by zero")]
+  DivisionByZero,
+  #[fail(display = "recursive definition of `{}`", _0)]
+  RecursiveDefinition(String),
+  #[fail(display = "FOR has no matching ROF")]
+  UnterminatedFor,
+  #[fail(display = "ROF without a matching FOR")]
+  UnmatchedRof,
+  #[fail(display = "FOR block expands past the maximum length of {}", _0)]
+  ForTooLong(usize),
+  #[fail(display = "the environment has a core size of 0")]
+  EmptyCore,
+}
+
+/// An error pointing at the offending token of a line of source
+#[derive(Debug, Clone, PartialEq, Eq, Fail)]
+pub struct ParseError {
+  pub kind: ParseErrorKind,
+  /// Line number, starting from 1
+  pub line: usize,
+  /// Column of the offending token, starting from 1
+  pub column: usize,
+  /// The offending token, empty if the line ended unexpectedly
+  pub token: String,
+  /// Description of what was expected in place of `token`
+  pub expected: Option<&'static str>,
+  /// The line of source that contains the error
+  pub source: String,
+}
+
+impl ParseError {
+  /// Create an error for the token starting at byte `offset` of `text`
+  pub(super) fn new(
+    kind: ParseErrorKind,
+    line: usize,
+    text: &str,
+    offset: usize,
+    expected: Option<&'static str>,
+  ) -> Self {
+    let rest = &text[offset..];
+    let token = match rest.chars().next() {
+      Some(c) if is_word_char(c) => rest.chars().take_while(|&c| is_word_char(c)).collect(),
+      Some(c) => c.to_string(),
+      None => String::new(),
+    };
+
+    ParseError {
+      kind,
+      line,
+      column: text[..offset].chars().count() + 1,
+      token,
+      expected,
+      source: text.to_string(),
+    }
+  }
+
+  /// Create an error for the first occurrence of `token` in `text`, or for
+  /// the start of the line if it doesn't occur
+  pub(super) fn at_token(
+    kind: ParseErrorKind,
+    line: usize,
+    text: &str,
+    token: &str,
+    expected: Option<&'static str>,
+  ) -> Self {
+    let offset = find_token(text, token)
+      .or_else(|| text.find(|c: char| !c.is_whitespace()))
+      .unwrap_or(0);
+    ParseError::new(kind, line, text, offset, expected)
+  }
+
+  pub(super) fn from_eval(error: EvalError, line: usize, text: &str) -> Self {
+    match error {
+      EvalError::UndefinedLabel(label) => ParseError::at_token(
+        ParseErrorKind::UndefinedLabel(label.clone()),
+        line,
+        text,
+        &label,
+        None,
+      ),
+      EvalError::DivisionByZero => {
+        let token = if text.contains('/') { "/" } else { "%" };
+        ParseError::at_token(ParseErrorKind::DivisionByZero, line, text, token, None)
+      }
+      EvalError::Recursive(label) => ParseError::at_token(
+        ParseErrorKind::RecursiveDefinition(label.clone()),
+        line,
+        text,
+        &label,
+        None,
+      ),
+    }
+  }
+
+  /// Render the line of source containing the error with the offending token
+  /// underlined
+  pub fn snippet(&self) -> String {
+    let line_number = self.line.to_string();
+    let gutter = " ".repeat(line_number.len());
+    // keep tabs so that the caret lines up with the source
+    let padding: String = self
+      .source
+      .chars()
+      .take(self.column - 1)
+      .map(|c| if c == '\t' { '\t' } else { ' ' })
+      .collect();
+    let carets = "^".repeat(self.token.chars().count().max(1));
+
+    format!(
+      "{} |\n{} | {}\n{} | {}{}",
+      gutter, line_number, self.source, gutter, padding, carets
+    )
+  }
+}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "{} on line {}, column {}",
+      self.kind, self.line, self.column
+    )?;
+
+    if let Some(expected) = self.expected {
+      if self.token.is_empty() {
+        write!(f, ": expected {}, found the end of the line", expected)?;
+      } else {
+        write!(f, ": expected {}, found `{}`", expected, self.token)?;
+      }
+    }
+
+    write!(f, "\n{}", self.snippet())
+  }
+}
+
+fn is_word_char(c: char) -> bool {
+  c.is_alphanumeric() || c == '_'
+}
+
+/// Find the byte offset of `token` in `text`, ignoring ASCII case. Word
+/// tokens only match whole words
+fn find_token(text: &str, token: &str) -> Option<usize> {
+  if token.is_empty() {
+    return None;
+  }
+
+  let is_word = token.chars().all(is_word_char);
+
+  text
+    .char_indices()
+    .map(|(offset, _)| offset)
+    .find(|&offset| {
+      let mut rest = text[offset..].chars();
+      if !token
+        .chars()
+        .all(|t| rest.next().is_some_and(|c| c.eq_ignore_ascii_case(&t)))
+      {
+        return false;
+      }
+
+      let before = text[..offset].chars().next_back();
+      let after = rest.next();
+      !is_word || !(before.is_some_and(is_word_char) || after.is_some_and(is_word_char))
+    })
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_token_location() {
+    let error = ParseError::at_token(
+      ParseErrorKind::UndefinedLabel("bomb".to_string()),
+      3,
+      "bomb2  JMP bomb",
+      "bomb",
+      None,
+    );
+    assert_eq!(error.column, 12);
+    assert_eq!(error.token, "bomb");
+
+    let error = ParseError::new(
+      ParseErrorKind::Syntax,
+      1,
+      "MOV 0,",
+      6,
+      Some("an expression"),
+    );
+    assert_eq!(error.column, 7);
+    assert_eq!(error.token, "");
+  }
+
+  #[test]
+  fn test_token_location_non_ascii() {
+    // 'İ' lowercases to two chars, which used to shift the offset
+    let error = ParseError::at_token(
+      ParseErrorKind::UndefinedLabel("BOMB".to_string()),
+      1,
+      "İ; é JMP bomb",
+      "BOMB",
+      None,
+    );
+    assert_eq!(error.column, 10);
+    assert_eq!(error.token, "bomb");
+  }
+
+  #[test]
+  fn test_display() {
+    let error = ParseError::new(
+      ParseErrorKind::Syntax,
+      12,
+      "\tMOV 0, ?1",
+      8,
+      Some("an expression"),
+    );
+    assert_eq!(
+      error.to_string(),
+      "syntax error on line 12, column 9: expected an expression, found `?`
+   |
+12 | \tMOV 0, ?1
+   | \t       ^"
+    );
+  }
+}
@@ -0,0 +1,289 @@
+//! Redcode field expressions
+use {
+  super::{EXPECTED_CLOSING_PAREN, EXPECTED_EXPRESSION},
+  nom::{types::CompleteStr, *},
+  std::str::FromStr,
+};
+
+/// An unevaluated field expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+  Number(i64),
+  Label(String),
+  Unary(UnaryOp, Box<Expr>),
+  Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+  Negate,
+  Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+  Add,
+  Sub,
+  Mul,
+  Div,
+  Mod,
+  Eq,
+  Ne,
+  Lt,
+  Gt,
+  Le,
+  Ge,
+  And,
+  Or,
+}
+
+/// An error encountered while evaluating an `Expr`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvalError {
+  UndefinedLabel(String),
+  DivisionByZero,
+  /// A constant was defined in terms of itself
+  Recursive(String),
+}
+
+impl Expr {
+  /// Evaluate the expression, looking up the values of labels with `lookup`
+  pub fn eval<F>(&self, lookup: &F) -> Result<i64, EvalError>
+  where
+    F: Fn(&str) -> Result<i64, EvalError>,
+  {
+    match *self {
+      Expr::Number(value) => Ok(value),
+      Expr::Label(ref label) => lookup(label),
+      Expr::Unary(op, ref expr) => {
+        let value = expr.eval(lookup)?;
+        Ok(match op {
+          UnaryOp::Negate => value.wrapping_neg(),
+          UnaryOp::Not => (value == 0) as i64,
+        })
+      }
+      Expr::Binary(op, ref lhs, ref rhs) => {
+        let lhs = lhs.eval(lookup)?;
+        let rhs = rhs.eval(lookup)?;
+        match op {
+          BinaryOp::Add => Ok(lhs.wrapping_add(rhs)),
+          BinaryOp::Sub => Ok(lhs.wrapping_sub(rhs)),
+          BinaryOp::Mul => Ok(lhs.wrapping_mul(rhs)),
+          BinaryOp::Div => lhs.checked_div(rhs).ok_or(EvalError::DivisionByZero),
+          BinaryOp::Mod => lhs.checked_rem(rhs).ok_or(EvalError::DivisionByZero),
+          BinaryOp::Eq => Ok((lhs == rhs) as i64),
+          BinaryOp::Ne => Ok((lhs != rhs) as i64),
+          BinaryOp::Lt => Ok((lhs < rhs) as i64),
+          BinaryOp::Gt => Ok((lhs > rhs) as i64),
+          BinaryOp::Le => Ok((lhs <= rhs) as i64),
+          BinaryOp::Ge => Ok((lhs >= rhs) as i64),
+          BinaryOp::And => Ok((lhs != 0 && rhs != 0) as i64),
+          BinaryOp::Or => Ok((lhs != 0 || rhs != 0) as i64),
+        }
+      }
+    }
+  }
+}
+
+/// Build a left associative chain of binary operations
+fn fold_binary(lhs: Expr, rest: Vec<(BinaryOp, Expr)>) -> Expr {
+  rest.into_iter().fold(lhs, |lhs, (op, rhs)| {
+    Expr::Binary(op, Box::new(lhs), Box::new(rhs))
+  })
+}
+
+fn is_identifier_char(c: char) -> bool {
+  c.is_alphanumeric() || c == '_'
+}
+
+named!(
+  pub parse_identifier<CompleteStr, CompleteStr>,
+  recognize!(pair!(
+    alt!(alpha1 | tag!("_")),
+    take_while!(is_identifier_char)
+  ))
+);
+
+/// Parse an operand that must follow an operator, failing for good if it is
+/// missing
+macro_rules! expect (
+  ($i:expr, $f:expr) => (
+    return_error!($i, ErrorKind::Custom(EXPECTED_EXPRESSION), call!($f))
+  );
+);
+
+// Operators from lowest to highest precedence
+named!(
+  pub parse_expr<CompleteStr, Expr>,
+  do_parse!(
+    lhs: parse_and
+      >> rest: many0!(pair!(
+        preceded!(space0, map!(tag!("||"), |_| BinaryOp::Or)),
+        preceded!(space0, expect!(parse_and))
+      ))
+      >> (fold_binary(lhs, rest))
+  )
+);
+
+named!(
+  parse_and<CompleteStr, Expr>,
+  do_parse!(
+    lhs: parse_comparison
+      >> rest: many0!(pair!(
+        preceded!(space0, map!(tag!("&&"), |_| BinaryOp::And)),
+        preceded!(space0, expect!(parse_comparison))
+      ))
+      >> (fold_binary(lhs, rest))
+  )
+);
+
+named!(
+  parse_comparison<CompleteStr, Expr>,
+  do_parse!(
+    lhs: parse_sum
+      >> rest: many0!(pair!(
+        preceded!(space0, parse_comparison_op),
+        preceded!(space0, expect!(parse_sum))
+      ))
+      >> (fold_binary(lhs, rest))
+  )
+);
+
+named!(
+  parse_comparison_op<CompleteStr, BinaryOp>,
+  alt_complete!(
+    map!(tag!("=="), |_| BinaryOp::Eq)
+      | map!(tag!("!="), |_| BinaryOp::Ne)
+      | map!(tag!("<="), |_| BinaryOp::Le)
+      | map!(tag!(">="), |_| BinaryOp::Ge)
+      | map!(char!('<'), |_| BinaryOp::Lt)
+      | map!(char!('>'), |_| BinaryOp::Gt)
+  )
+);
+
+named!(
+  parse_sum<CompleteStr, Expr>,
+  do_parse!(
+    lhs: parse_product
+      >> rest: many0!(pair!(
+        preceded!(
+          space0,
+          alt!(map!(char!('+'), |_| BinaryOp::Add) | map!(char!('-'), |_| BinaryOp::Sub))
+        ),
+        preceded!(space0, expect!(parse_product))
+      ))
+      >> (fold_binary(lhs, rest))
+  )
+);
+
+named!(
+  parse_product<CompleteStr, Expr>,
+  do_parse!(
+    lhs: parse_unary
+      >> rest: many0!(pair!(
+        preceded!(
+          space0,
+          alt!(
+            map!(char!('*'), |_| BinaryOp::Mul)
+              | map!(char!('/'), |_| BinaryOp::Div)
+              | map!(char!('%'), |_| BinaryOp::Mod)
+          )
+        ),
+        preceded!(space0, expect!(parse_unary))
+      ))
+      >> (fold_binary(lhs, rest))
+  )
+);
+
+named!(
+  parse_unary<CompleteStr, Expr>,
+  alt!(
+    map!(preceded!(pair!(char!('-'), space0), expect!(parse_unary)), |e| {
+      Expr::Unary(UnaryOp::Negate, Box::new(e))
+    }) | map!(preceded!(pair!(char!('!'), space0), expect!(parse_unary)), |e| {
+      Expr::Unary(UnaryOp::Not, Box::new(e))
+    }) | preceded!(pair!(char!('+'), space0), expect!(parse_unary))
+      | parse_atom
+  )
+);
+
+named!(
+  parse_atom<CompleteStr, Expr>,
+  alt!(
+    map_res!(digit, |s: CompleteStr| FromStr::from_str(s.0).map(Expr::Number))
+      | map!(parse_identifier, |s| Expr::Label(s.0.to_string()))
+      | delimited!(
+        pair!(char!('('), space0),
+        expect!(parse_expr),
+        preceded!(
+          space0,
+          return_error!(ErrorKind::Custom(EXPECTED_CLOSING_PAREN), char!(')'))
+        )
+      )
+  )
+);
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn eval(s: &str) -> Result<i64, EvalError> {
+    let (rest, expr) = parse_expr(CompleteStr(s)).unwrap();
+    assert!(rest.is_empty(), "unparsed input: {:?}", rest);
+    expr.eval(&|label| match label {
+      "step" => Ok(4),
+      _ => Err(EvalError::UndefinedLabel(label.to_string())),
+    })
+  }
+
+  #[test]
+  fn test_arithmetic() {
+    let cases = [
+      ("1", 1),
+      ("-1", -1),
+      ("- 1", -1),
+      ("+3", 3),
+      ("--2", 2),
+      ("1+2*3", 7),
+      ("(1+2)*3", 9),
+      ("10-4-3", 3),
+      ("7/2", 3),
+      ("-7%3", -1),
+      ("step*2+1", 9),
+      ("step * (2 - 5)", -12),
+    ];
+    for (s, expected) in cases.iter() {
+      assert_eq!(eval(s), Ok(*expected), "{}", s);
+    }
+  }
+
+  #[test]
+  fn test_logical() {
+    let cases = [
+      ("1==1", 1),
+      ("1!=1", 0),
+      ("2<3", 1),
+      ("2>3", 0),
+      ("3<=3", 1),
+      ("2>=3", 0),
+      ("1&&0", 0),
+      ("1||0", 1),
+      ("!0", 1),
+      ("!step", 0),
+      ("1+1==2 && step>3", 1),
+    ];
+    for (s, expected) in cases.iter() {
+      assert_eq!(eval(s), Ok(*expected), "{}", s);
+    }
+  }
+
+  #[test]
+  fn test_eval_errors() {
+    assert_eq!(eval("1/0"), Err(EvalError::DivisionByZero));
+    assert_eq!(eval("1%(step-4)"), Err(EvalError::DivisionByZero));
+    assert_eq!(
+      eval("bomb+1"),
+      Err(EvalError::UndefinedLabel("bomb".to_string()))
+    );
+  }
+}
@@ -1,19 +1,543 @@
+mod error;
+mod expr;
+mod preprocess;
+
+pub use self::error::{ParseError, ParseErrorKind};
+
 use {
-  nom::*,
+  self::expr::{parse_expr, parse_identifier, EvalError, Expr},
+  nom::{types::CompleteStr, *},
   redcode::{AddressingMode::*, OpCode::*, OpMode::*, *},
-  std::str::FromStr,
+  simulation::{
+    MARS_DEFAULT_MAX_CYCLES, MARS_DEFAULT_MAX_LENGTH, MARS_DEFAULT_MAX_PROCESSES,
+    MARS_DEFAULT_MIN_DISTANCE, MARS_DEFAULT_P_SPACE_SIZE, MARS_DEFAULT_SIZE,
+  },
+  std::collections::HashMap,
 };
 
+const DEFAULT_WARRIORS: usize = 2;
+const DEFAULT_ROUNDS: usize = 1;
+
+// Codes of the errors returned at the points where the parser commits to a
+// construct, each describing what was expected there
+const EXPECTED_STATEMENT: u32 = 1;
+const EXPECTED_MODIFIER: u32 = 2;
+const EXPECTED_EXPRESSION: u32 = 3;
+const EXPECTED_CLOSING_PAREN: u32 = 4;
+const EXPECTED_END_OF_LINE: u32 = 5;
+
+fn describe_expected(code: u32) -> Option<&'static str> {
+  match code {
+    EXPECTED_STATEMENT => Some("an opcode or pseudo-op"),
+    EXPECTED_MODIFIER => Some("a modifier (A, B, AB, BA, F, X or I)"),
+    EXPECTED_EXPRESSION => Some("an expression"),
+    EXPECTED_CLOSING_PAREN => Some("`)`"),
+    EXPECTED_END_OF_LINE => Some("the end of the line"),
+    _ => None,
+  }
+}
+
+/// Turn the error returned by `parse_line` for `text` into a `ParseError`
+/// pointing at the innermost point the parser gave up at
+fn syntax_error(line: usize, text: &str, error: Err<CompleteStr, u32>) -> ParseError {
+  let errors = match error {
+    Err::Error(Context::Code(input, kind)) | Err::Failure(Context::Code(input, kind)) => {
+      vec![(input, kind)]
+    }
+    Err::Error(Context::List(errors)) | Err::Failure(Context::List(errors)) => errors,
+    Err::Incomplete(_) => vec![],
+  };
+
+  let (offset, expected) = errors
+    .iter()
+    .find_map(|(input, kind)| match *kind {
+      ErrorKind::Custom(code) => Some((text.len() - input.len(), describe_expected(code))),
+      _ => None,
+    })
+    .unwrap_or((0, None));
+
+  ParseError::new(ParseErrorKind::Syntax, line, text, offset, expected)
+}
+
+/// Settings of the Mars a program is assembled for, available to programs
+/// through predefined symbols like `CORESIZE`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Environment {
+  /// Size of the core that field values are folded into
+  pub core_size: usize,
+  pub p_space_size: usize,
+  pub max_processes: usize,
+  pub max_cycles: usize,
+  pub max_length: usize,
+  pub min_distance: usize,
+  /// Number of warriors in the battle
+  pub warriors: usize,
+  pub rounds: usize,
+}
+
+impl Environment {
+  /// Return the value of a predefined symbol
+  fn predefined(&self, name: &str) -> Option<i64> {
+    let value = match name {
+      "CORESIZE" => self.core_size,
+      "PSPACESIZE" => self.p_space_size,
+      "MAXPROCESSES" => self.max_processes,
+      "MAXCYCLES" => self.max_cycles,
+      "MAXLENGTH" => self.max_length,
+      "MINDISTANCE" => self.min_distance,
+      "WARRIORS" => self.warriors,
+      "ROUNDS" => self.rounds,
+      _ => return None,
+    };
+    Some(value as i64)
+  }
+}
+
+impl Default for Environment {
+  fn default() -> Self {
+    Environment {
+      core_size: MARS_DEFAULT_SIZE,
+      p_space_size: MARS_DEFAULT_P_SPACE_SIZE,
+      max_processes: MARS_DEFAULT_MAX_PROCESSES,
+      max_cycles: MARS_DEFAULT_MAX_CYCLES,
+      max_length: MARS_DEFAULT_MAX_LENGTH,
+      min_distance: MARS_DEFAULT_MIN_DISTANCE,
+      warriors: DEFAULT_WARRIORS,
+      rounds: DEFAULT_ROUNDS,
+    }
+  }
+}
+
+/// Maximum depth that `EQU` constants will be expanded to before they are
+/// considered to be recursive
+const MAX_EXPANSION_DEPTH: usize = 64;
+
+/// Pseudo-ops, which like opcodes can not be used as labels
+const PSEUDO_OPS: &[&str] = &["EQU", "ORG", "END", "FOR", "ROF"];
+
+/// A field whose value has not been resolved yet
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RawField {
+  mode: AddressingMode,
+  value: Expr,
+}
+
+impl Default for RawField {
+  fn default() -> Self {
+    RawField {
+      mode: AddressingMode::default(),
+      value: Expr::Number(0),
+    }
+  }
+}
+
+/// An instruction whose fields have not been resolved yet
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RawInstruction {
+  op: OpField,
+  a: RawField,
+  b: RawField,
+}
+
+impl RawInstruction {
+  /// Resolve the instruction found at `offset`, turning label references into
+  /// offsets relative to it and folding the fields into the core
+  fn resolve(
+    &self,
+    offset: usize,
+    symbols: &Symbols,
+    env: &Environment,
+  ) -> Result<Instruction, EvalError> {
+    let resolve_field = |field: &RawField| {
+      Ok(Field {
+        mode: field.mode,
+        value: fold(symbols.eval(&field.value, offset)?, env.core_size),
+      })
+    };
+
+    Ok(Instruction {
+      op: self.op,
+      a: resolve_field(&self.a)?,
+      b: resolve_field(&self.b)?,
+    })
+  }
+}
+
+/// Return the modifier ICWS'94 gives an instruction written without one,
+/// which depends on the opcode and on which operands are immediate
+fn default_modifier(code: OpCode, a: AddressingMode, b: AddressingMode) -> OpMode {
+  match code {
+    Dat | Nop => F,
+    Jmp | Jmz | Jmn | Djn | Spl => B,
+    Slt | Ldp | Stp => {
+      if a.is_immediate() {
+        AB
+      } else {
+        B
+      }
+    }
+    Mov | Seq | Sne | Cmp | Add | Sub | Mul | Div | Mod => {
+      if a.is_immediate() {
+        AB
+      } else if b.is_immediate() {
+        B
+      } else if let Mov | Seq | Sne | Cmp = code {
+        I
+      } else {
+        F
+      }
+    }
+  }
+}
+
+/// A statement following a line's labels
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Statement {
+  Instruction(RawInstruction),
+  /// Define the line's labels as a constant
+  Equ(Expr),
+  /// Set the start offset
+  Org(Expr),
+  /// Stop assembling, optionally setting the start offset
+  End(Option<Expr>),
+  /// Repeat the following lines up to the matching `ROF`
+  For(Expr),
+  Rof,
+}
+
+/// A single line of source
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Line {
+  labels: Vec<String>,
+  statement: Option<Statement>,
+}
+
+/// Labels and `EQU` constants defined by a program
+#[derive(Debug, Clone, Default)]
+struct Symbols {
+  labels: HashMap<String, usize>,
+  constants: HashMap<String, Expr>,
+  env: Environment,
+}
+
+impl Symbols {
+  fn new(env: &Environment) -> Self {
+    Symbols {
+      env: *env,
+      ..Symbols::default()
+    }
+  }
+
+  fn is_defined(&self, name: &str) -> bool {
+    self.labels.contains_key(name) || self.constants.contains_key(name)
+  }
+
+  /// Define a label that refers to the instruction at `offset`
+  fn define_label(&mut self, label: String, offset: usize) -> Result<(), ParseErrorKind> {
+    if self.is_defined(&label) {
+      return Err(ParseErrorKind::DuplicateLabel(label));
+    }
+    self.labels.insert(label, offset);
+    Ok(())
+  }
+
+  /// Define a label that stands for `expr`
+  fn define_constant(&mut self, label: String, expr: Expr) -> Result<(), ParseErrorKind> {
+    if self.is_defined(&label) {
+      return Err(ParseErrorKind::DuplicateLabel(label));
+    }
+    self.constants.insert(label, expr);
+    Ok(())
+  }
+
+  /// Evaluate `expr` as seen from the instruction at `offset`. Constants are
+  /// expanded in place, so labels inside of them are also relative to `offset`.
+  /// Labels and constants shadow predefined symbols
+  fn eval(&self, expr: &Expr, offset: usize) -> Result<i64, EvalError> {
+    self.eval_with_depth(expr, offset, 0)
+  }
+
+  fn eval_with_depth(&self, expr: &Expr, offset: usize, depth: usize) -> Result<i64, EvalError> {
+    expr.eval(&|name: &str| {
+      if let Some(&target) = self.labels.get(name) {
+        Ok(target as i64 - offset as i64)
+      } else if let Some(constant) = self.constants.get(name) {
+        if depth < MAX_EXPANSION_DEPTH {
+          self.eval_with_depth(constant, offset, depth + 1)
+        } else {
+          Err(EvalError::Recursive(name.to_string()))
+        }
+      } else if name == "CURLINE" {
+        Ok(offset as i64)
+      } else {
+        self
+          .env
+          .predefined(name)
+          .ok_or_else(|| EvalError::UndefinedLabel(name.to_string()))
+      }
+    })
+  }
+}
+
+/// Parse a program for a default sized core
+pub fn parse_program(input: &str) -> Result<Warrior, ParseError> {
+  parse_program_with_environment(input, &Environment::default())
+}
+
+/// Parse a program, resolving labels to offsets relative to the instruction
+/// they are referenced from and evaluating field expressions modulo the core
+/// size of `env`
+///
+/// # Returns
+/// The first error in the program, see `parse_program_collecting_errors` to
+/// get all of them
+pub fn parse_program_with_environment(
+  input: &str,
+  env: &Environment,
+) -> Result<Warrior, ParseError> {
+  parse_program_collecting_errors(input, env).map_err(|mut errors| errors.remove(0))
+}
+
+/// Parse a program like `parse_program_with_environment`, carrying on past
+/// errors so that every error in the program can be reported at once
+///
+/// # Returns
+/// The errors ordered by their position in `input`, or a single error at the
+/// start of `input` if `env` has a core size of 0
+pub fn parse_program_collecting_errors(
+  input: &str,
+  env: &Environment,
+) -> Result<Warrior, Vec<ParseError>> {
+  // field values are folded into the core
+  if env.core_size == 0 {
+    let text = input.lines().next().unwrap_or("");
+    return Err(vec![ParseError::new(
+      ParseErrorKind::EmptyCore,
+      1,
+      text,
+      0,
+      None,
+    )]);
+  }
+
+  let mut warrior = Warrior::default();
+  let mut symbols = Symbols::new(env);
+  let mut instructions = vec![];
+  let mut start = None;
+  let mut errors = vec![];
+
+  // strip comments, recording header comments along the way
+  let lines = input
+    .lines()
+    .enumerate()
+    .filter_map(|(i, text)| {
+      let code = match text.find(';') {
+        Some(index) => {
+          if text[..index].trim().is_empty() {
+            parse_header_comment(&mut warrior, &text[index + 1..]);
+          }
+          &text[..index]
+        }
+        None => text,
+      };
+
+      if code.trim().is_empty() {
+        None
+      } else {
+        Some((i + 1, code.to_string()))
+      }
+    })
+    .collect();
+
+  for (line, text) in preprocess::expand(lines, env, &mut errors) {
+    let parsed = match parse_line(CompleteStr(&text)) {
+      Ok((_, parsed)) => parsed,
+      Err(e) => {
+        errors.push(syntax_error(line, &text, e));
+        continue;
+      }
+    };
+
+    match parsed.statement {
+      Some(Statement::Equ(expr)) => {
+        if parsed.labels.is_empty() {
+          errors.push(ParseError::at_token(
+            ParseErrorKind::Syntax,
+            line,
+            &text,
+            "EQU",
+            Some("a label to define"),
+          ));
+        }
+        for label in parsed.labels {
+          if let Err(kind) = symbols.define_constant(label.clone(), expr.clone()) {
+            errors.push(ParseError::at_token(kind, line, &text, &label, None));
+          }
+        }
+      }
+      statement => {
+        // labels refer to the next instruction, even if it is on a later line
+        for label in parsed.labels {
+          if let Err(kind) = symbols.define_label(label.clone(), instructions.len()) {
+            errors.push(ParseError::at_token(kind, line, &text, &label, None));
+          }
+        }
+
+        match statement {
+          Some(Statement::Instruction(instruction)) => instructions.push((line, text, instruction)),
+          Some(Statement::Org(expr)) => start = Some((line, text, expr)),
+          Some(Statement::End(expr)) => {
+            start = expr.map(|expr| (line, text, expr)).or(start);
+            break;
+          }
+          // FOR/ROF blocks have already been expanded
+          _ => {}
+        }
+      }
+    }
+  }
+
+  let instructions = instructions
+    .iter()
+    .enumerate()
+    .filter_map(|(offset, (line, text, instruction))| {
+      match instruction.resolve(offset, &symbols, env) {
+        Ok(instruction) => Some(instruction),
+        Err(e) => {
+          errors.push(ParseError::from_eval(e, *line, text));
+          None
+        }
+      }
+    })
+    .collect();
+
+  // the start offset is relative to the first instruction
+  let start = match start {
+    Some((line, text, expr)) => match symbols.eval(&expr, 0) {
+      Ok(value) => fold(value, env.core_size),
+      Err(e) => {
+        errors.push(ParseError::from_eval(e, line, &text));
+        0
+      }
+    },
+    None => 0,
+  };
+
+  if !errors.is_empty() {
+    // show the comments that were stripped before parsing, unless the line
+    // was rewritten by a FOR block and the columns no longer line up
+    let sources: Vec<&str> = input.lines().collect();
+    for error in &mut errors {
+      let source = sources[error.line - 1];
+      if source.starts_with(error.source.as_str()) {
+        error.source = source.to_string();
+      }
+    }
+    errors.sort_by_key(|error| (error.line, error.column));
+    return Err(errors);
+  }
+
+  Ok(Warrior {
+    instructions,
+    start,
+    ..warrior
+  })
+}
+
+/// Record the contents of a header comment like `;name Imp` in a warrior.
+/// Comments that aren't headers are ignored
+fn parse_header_comment(warrior: &mut Warrior, comment: &str) {
+  let comment = comment.trim();
+  let (keyword, value) = match comment.find(char::is_whitespace) {
+    Some(index) => (&comment[..index], comment[index..].trim()),
+    None => (comment, ""),
+  };
+  let value = value.to_string();
+
+  match keyword.to_lowercase().as_str() {
+    "redcode" => warrior.redcode = Some(value),
+    keyword if keyword.starts_with("redcode-") => {
+      warrior.redcode = Some(keyword["redcode-".len()..].to_string())
+    }
+    "name" => warrior.name = Some(value),
+    "author" => warrior.author = Some(value),
+    "version" => warrior.version = Some(value),
+    "date" => warrior.date = Some(value),
+    "strategy" => {
+      warrior.strategy = Some(match warrior.strategy.take() {
+        Some(strategy) => strategy + "\n" + &value,
+        None => value,
+      })
+    }
+    _ => {}
+  }
+}
+
+/// Fold a value into the range `[0, size)`
+fn fold(value: i64, size: usize) -> Address {
+  let size = size as i64;
+  (((value % size) + size) % size) as Address
+}
+
+/// Return true if `s` can not be used as a label
+fn is_reserved(s: &str) -> bool {
+  let is_opcode = parse_opcode(CompleteStr(s))
+    .map(|(rest, _)| rest.is_empty())
+    .unwrap_or(false);
+
+  is_opcode || PSEUDO_OPS.iter().any(|op| op.eq_ignore_ascii_case(s))
+}
+
 named!(
-  pub parse_program<&str, Vec<Instruction>>,
+  parse_line<CompleteStr, Line>,
   do_parse!(
-    instructions: separated_list!(line_ending, parse_instruction)
-    >> (instructions)
+    space0
+      >> labels: many0!(terminated!(parse_label, space0))
+      >> statement: opt!(parse_statement)
+      >> space0
+      >> return_error!(
+        ErrorKind::Custom(if statement.is_some() {
+          EXPECTED_END_OF_LINE
+        } else {
+          EXPECTED_STATEMENT
+        }),
+        eof!()
+      )
+      >> (Line { labels, statement })
   )
 );
 
 named!(
-  parse_instruction<&str, Instruction>,
+  parse_statement<CompleteStr, Statement>,
+  alt!(
+    map!(
+      preceded!(tag_no_case!("EQU"), expect_expr),
+      Statement::Equ
+    ) | map!(
+      preceded!(tag_no_case!("ORG"), expect_expr),
+      Statement::Org
+    ) | map!(
+      preceded!(tag_no_case!("END"), opt!(preceded!(space1, parse_expr))),
+      Statement::End
+    ) | map!(
+      preceded!(tag_no_case!("FOR"), expect_expr),
+      Statement::For
+    ) | map!(tag_no_case!("ROF"), |_| Statement::Rof)
+      | map!(parse_instruction, Statement::Instruction)
+  )
+);
+
+named!(
+  parse_label<CompleteStr, String>,
+  do_parse!(
+    label: verify!(parse_identifier, |s: CompleteStr| !is_reserved(s.0))
+      >> opt!(char!(':'))
+      >> (label.0.to_string())
+  )
+);
+
+named!(
+  parse_instruction<CompleteStr, RawInstruction>,
   do_parse!(
     op: parse_opfield
       >> space0
@@ -21,12 +545,19 @@ named!(
       >> space0
       // use the default if it can't be parsed
       >> b: map!(maybe_parse_b_field, |r| r.unwrap_or_default())
-      >> (Instruction { op, a, b })
+      >> (RawInstruction {
+        op: OpField {
+          code: op.0,
+          mode: op.1.unwrap_or_else(|| default_modifier(op.0, a.mode, b.mode)),
+        },
+        a,
+        b,
+      })
   )
 );
 
 named!(
-  parse_opcode<&str, OpCode>,
+  parse_opcode<CompleteStr, OpCode>,
   alt_complete!(
     map!(tag_no_case!("DAT"), |_| Dat)
       | map!(tag_no_case!("MOV"), |_| Mov)
@@ -51,7 +582,7 @@ named!(
 );
 
 named!(
-  parse_addressing_mode<&str, AddressingMode>,
+  parse_addressing_mode<CompleteStr, AddressingMode>,
   alt_complete!(
     map!(char!('#'), |_| Immediate)
       | map!(char!('$'), |_| Direct)
@@ -77,7 +608,7 @@ named!(
 );
 
 named!(
-  parse_opmode<&str, OpMode>,
+  parse_opmode<CompleteStr, OpMode>,
   alt_complete!(
     map!(tag_no_case!("AB"), |_| AB)
       | map!(tag_no_case!("BA"), |_| BA)
@@ -90,7 +621,7 @@ named!(
 );
 
 named!(
-  maybe_parse_b_field<&str, Option<Field>>,
+  maybe_parse_b_field<CompleteStr, Option<RawField>>,
   opt!(
     do_parse!(
       tag!(",")
@@ -102,43 +633,59 @@ named!(
 );
 
 named!(
-  parse_opfield<&str, OpField>,
+  parse_opfield<CompleteStr, (OpCode, Option<OpMode>)>,
   do_parse!(
     code: parse_opcode
-      >> mode: map!(maybe_parse_opfield_opmode, |r| r.unwrap_or_default())
-      >> (OpField { code, mode })
+      >> mode: maybe_parse_opfield_opmode
+      >> (code, mode)
   )
 );
 
 named!(
-  maybe_parse_opfield_opmode<&str, Option<OpMode>>,
-  opt!(preceded!(char!('.'), parse_opmode))
+  maybe_parse_opfield_opmode<CompleteStr, Option<OpMode>>,
+  opt!(preceded!(
+    char!('.'),
+    return_error!(ErrorKind::Custom(EXPECTED_MODIFIER), parse_opmode)
+  ))
 );
 
 named!(
-  parse_field<&str, Field>,
+  parse_field<CompleteStr, RawField>,
   do_parse!(
     mode: map!(opt!(parse_addressing_mode), |r| r.unwrap_or_default())
     >> space0
-    >> value: parse_field_value
-    >> (Field { mode, value })
+    >> value: return_error!(ErrorKind::Custom(EXPECTED_EXPRESSION), parse_expr)
+    >> (RawField { mode, value })
   )
 );
 
+// The expression following a pseudo-op, which must be separated from it
 named!(
-  parse_field_value<&str, Address>,
-  map!(digit, |s| FromStr::from_str(s).expect("fasdfasd"))
+  expect_expr<CompleteStr, Expr>,
+  return_error!(
+    ErrorKind::Custom(EXPECTED_EXPRESSION),
+    preceded!(space1, parse_expr)
+  )
 );
 
 #[cfg(test)]
 mod test {
   use super::*;
 
+  /// Parse `input`, returning the kind and position of its first error
+  fn first_error(input: &str) -> (ParseErrorKind, usize, usize) {
+    let error = parse_program(input).unwrap_err();
+    (error.kind, error.line, error.column)
+  }
+
   #[test]
   fn test_parse_field_value() {
     let cases = [("123 ", 123), ("0 ", 0)];
     for (input, expected) in cases.iter() {
-      assert_eq!(parse_field_value(input).unwrap().1, *expected);
+      assert_eq!(
+        parse_expr(CompleteStr(input)).unwrap().1,
+        Expr::Number(*expected)
+      );
     }
   }
 
@@ -147,22 +694,22 @@ mod test {
     let cases = [
       (
         "#1 ",
-        Field {
+        RawField {
           mode: Immediate,
-          value: 1,
+          value: Expr::Number(1),
         },
       ),
       (
         "2 ",
-        Field {
+        RawField {
           mode: AddressingMode::default(),
-          value: 2,
+          value: Expr::Number(2),
         },
       ),
     ];
 
     for (input, expected) in cases.iter() {
-      assert_eq!(parse_field(input).unwrap().1, *expected);
+      assert_eq!(parse_field(CompleteStr(input)).unwrap().1, *expected);
     }
   }
 
@@ -172,37 +719,58 @@ mod test {
       // (" EOF", None), // FIXME: why isn't EOF detected
       (
         ", $222 ",
-        Some(Field {
+        Some(RawField {
           mode: Direct,
-          value: 222,
+          value: Expr::Number(222),
         }),
       ),
     ];
     for (input, expected) in cases.iter() {
-      assert_eq!(maybe_parse_b_field(input).unwrap().1, *expected);
+      assert_eq!(
+        maybe_parse_b_field(CompleteStr(input)).unwrap().1,
+        *expected
+      );
     }
   }
 
   #[test]
   fn test_parse_opfield() {
+    let cases = [("AdD.Ab ", (Add, Some(AB))), ("Mov ", (Mov, None))];
+    for (input, expected) in cases.iter() {
+      assert_eq!(parse_opfield(CompleteStr(input)).unwrap().1, *expected);
+    }
+  }
+
+  #[test]
+  fn test_default_modifiers() {
     let cases = [
-      (
-        "AdD.Ab ",
-        OpField {
-          code: Add,
-          mode: AB,
-        },
-      ),
-      (
-        "Mov ",
-        OpField {
-          code: Mov,
-          mode: OpMode::default(),
-        },
-      ),
+      ("DAT #0, #0", F),
+      ("NOP $1, $2", F),
+      ("MOV #1, $2", AB),
+      ("MOV $1, #2", B),
+      ("MOV $1, $2", I),
+      ("SEQ #1, @2", AB),
+      ("SNE $1, #2", B),
+      ("CMP $1, $2", I),
+      ("ADD #4, $3", AB),
+      ("SUB $1, #2", B),
+      ("MUL $1, $2", F),
+      ("DIV #1, #2", AB),
+      ("MOD @1, <2", F),
+      ("SLT #1, $2", AB),
+      ("SLT $1, #2", B),
+      ("LDP #1, $2", AB),
+      ("STP $1, $2", B),
+      ("JMP #1, $2", B),
+      ("JMZ $1, #2", B),
+      ("JMN $1, $2", B),
+      ("DJN $1, $2", B),
+      ("SPL #1, $2", B),
+      ("MOV.X #1, $2", X),
     ];
     for (input, expected) in cases.iter() {
-      assert_eq!(parse_opfield(input).unwrap().1, *expected);
+      let parsed = parse_instruction(CompleteStr(input)).unwrap().1;
+      assert_eq!(parsed.op.mode, *expected, "{}", input);
     }
   }
 
@@ -230,7 +798,7 @@ mod test {
       ("nOp", Nop),
     ];
     for (s, code) in values.iter() {
-      assert_eq!(parse_opcode(s).unwrap().1, *code);
+      assert_eq!(parse_opcode(CompleteStr(s)).unwrap().1, *code);
     }
   }
 
@@ -246,7 +814,7 @@ mod test {
       ("f ", F),
     ];
     for (s, mode) in values.iter() {
-      assert_eq!(parse_opmode(s).unwrap().1, *mode);
+      assert_eq!(parse_opmode(CompleteStr(s)).unwrap().1, *mode);
     }
   }
 
@@ -271,7 +839,13 @@ mod test {
     )];
 
     for (s, mode) in cases.iter() {
-      assert_eq!(parse_instruction(s).unwrap().1, *mode);
+      let parsed = parse_instruction(CompleteStr(s)).unwrap().1;
+      assert_eq!(
+        parsed
+          .resolve(0, &Symbols::default(), &Environment::default())
+          .unwrap(),
+        *mode
+      );
     }
   }
 
@@ -283,7 +857,7 @@ JMP    2
 DAT    #0, #0
 "#;
 
-    let parsed = parse_program(program).unwrap().1;
+    let parsed = parse_program(program).unwrap().instructions;
     assert_eq!(
       parsed,
       vec![
@@ -298,14 +872,297 @@ DAT    #0, #0
         ),
         Instruction::new(
           Jmp,
-          OpMode::default(),
+          B,
           AddressingMode::default(),
           2,
           AddressingMode::default(),
           Address::default()
         ),
-        Instruction::new(Dat, OpMode::default(), Immediate, 0, Immediate, 0),
+        Instruction::new(Dat, F, Immediate, 0, Immediate, 0),
       ]
     )
   }
+
+  #[test]
+  fn test_parse_line() {
+    let cases = [
+      ("", vec![], false),
+      ("start", vec!["start"], false),
+      ("bomb: DAT #0", vec!["bomb"], true),
+      ("a b MOV 0, 1", vec!["a", "b"], true),
+      ("  DATA_1 dat 0", vec!["DATA_1"], true),
+      ("mov.i 0, 1", vec![], true),
+    ];
+
+    for (s, labels, has_instruction) in cases.iter() {
+      let line = parse_line(CompleteStr(s)).unwrap().1;
+      assert_eq!(line.labels, *labels);
+      assert_eq!(line.statement.is_some(), *has_instruction);
+    }
+  }
+
+  #[test]
+  fn test_parse_program_with_labels() {
+    let program = r#"
+start   ADD.AB #4, bomb
+        MOV.I  bomb, @bomb
+        JMP    start
+bomb:   DAT    #0, #0
+"#;
+
+    let parsed = parse_program(program).unwrap().instructions;
+    assert_eq!(
+      parsed,
+      vec![
+        Instruction::new(Add, AB, Immediate, 4, Direct, 3),
+        Instruction::new(Mov, I, Direct, 2, BIndirect(IncrementMode::None), 2),
+        Instruction::new(Jmp, B, Direct, 7998, Direct, 0),
+        Instruction::new(Dat, F, Immediate, 0, Immediate, 0),
+      ]
+    )
+  }
+
+  #[test]
+  fn test_parse_program_label_errors() {
+    assert_eq!(
+      first_error("JMP nowhere"),
+      (ParseErrorKind::UndefinedLabel("nowhere".to_string()), 1, 5)
+    );
+    assert_eq!(
+      first_error("a DAT 0\na DAT 0"),
+      (ParseErrorKind::DuplicateLabel("a".to_string()), 2, 1)
+    );
+    assert_eq!(
+      first_error("MOV 0, 1\nMOV 0, ?"),
+      (ParseErrorKind::Syntax, 2, 8)
+    );
+  }
+
+  #[test]
+  fn test_parse_program_with_expressions() {
+    let program = r#"
+        DAT    #0, #-1
+        JMP    -1
+step    MOV    0, step*2+1
+        ADD    #(2+3)*4, step-2 > 0
+"#;
+
+    let parsed = parse_program(program).unwrap().instructions;
+    assert_eq!(
+      parsed,
+      vec![
+        Instruction::new(Dat, F, Immediate, 0, Immediate, 7999),
+        Instruction::new(Jmp, B, Direct, 7999, Direct, 0),
+        Instruction::new(Mov, I, Direct, 0, Direct, 1),
+        Instruction::new(Add, AB, Immediate, 20, Direct, 0),
+      ]
+    );
+
+    assert_eq!(
+      first_error("DAT 1/0"),
+      (ParseErrorKind::DivisionByZero, 1, 6)
+    );
+  }
+
+  #[test]
+  fn test_parse_program_with_environment() {
+    let env = Environment {
+      core_size: 55,
+      ..Environment::default()
+    };
+    let parsed = parse_program_with_environment("JMP -1, 56", &env)
+      .unwrap()
+      .instructions;
+    assert_eq!(
+      parsed,
+      vec![Instruction::new(Jmp, B, Direct, 54, Direct, 1)]
+    );
+  }
+
+  #[test]
+  fn test_parse_program_with_pseudo_ops() {
+    let program = r#"
+step    EQU    4
+dist    EQU    step*2
+        ORG    start
+bomb    DAT    #0, #0
+start   ADD    #step, bomb
+        MOV    bomb, @bomb
+        JMP    start, dist
+        END
+        this is not redcode
+"#;
+
+    let warrior = parse_program(program).unwrap();
+    assert_eq!(warrior.start, 1);
+    assert_eq!(
+      warrior.instructions,
+      vec![
+        Instruction::new(Dat, F, Immediate, 0, Immediate, 0),
+        Instruction::new(Add, AB, Immediate, 4, Direct, 7999),
+        Instruction::new(Mov, I, Direct, 7998, BIndirect(IncrementMode::None), 7998),
+        Instruction::new(Jmp, B, Direct, 7998, Direct, 8),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_end_sets_start() {
+    let warrior = parse_program("DAT 0\nloop JMP loop\nEND loop").unwrap();
+    assert_eq!(warrior.start, 1);
+    assert_eq!(warrior.instructions.len(), 2);
+
+    // without a start offset, execution begins with the first instruction
+    assert_eq!(parse_program("JMP 0").unwrap().start, 0);
+  }
+
+  #[test]
+  fn test_pseudo_op_errors() {
+    assert_eq!(
+      first_error("a EQU b\nb EQU a\nDAT a"),
+      (ParseErrorKind::RecursiveDefinition("a".to_string()), 3, 5)
+    );
+    assert_eq!(
+      first_error("a EQU 1\na DAT 0"),
+      (ParseErrorKind::DuplicateLabel("a".to_string()), 2, 1)
+    );
+    assert_eq!(first_error("EQU 1"), (ParseErrorKind::Syntax, 1, 1));
+  }
+
+  #[test]
+  fn test_parse_program_with_comments() {
+    let program = r#";redcode-94
+;name Dwarf
+;author A. K. Dewdney
+;strategy Bombs the core at regular intervals
+;strategy with DAT bombs
+; just a comment
+;version 2
+;date April 1984
+        ADD.AB #4, bomb ; comments after instructions are ignored
+        MOV.I  bomb, @bomb
+        JMP    -2
+bomb    DAT    #0, #0
+"#;
+
+    let warrior = parse_program(program).unwrap();
+    assert_eq!(warrior.instructions.len(), 4);
+    assert_eq!(warrior.redcode, Some("94".to_string()));
+    assert_eq!(warrior.name, Some("Dwarf".to_string()));
+    assert_eq!(warrior.author, Some("A. K. Dewdney".to_string()));
+    assert_eq!(warrior.version, Some("2".to_string()));
+    assert_eq!(warrior.date, Some("April 1984".to_string()));
+    assert_eq!(
+      warrior.strategy,
+      Some("Bombs the core at regular intervals\nwith DAT bombs".to_string())
+    );
+  }
+
+  #[test]
+  fn test_parse_program_without_header() {
+    let warrior = parse_program("MOV 0, 1 ;name not a header").unwrap();
+    assert_eq!(warrior.name, None);
+    assert_eq!(warrior.redcode, None);
+  }
+
+  #[test]
+  fn test_parse_program_with_for_and_predefined() {
+    let program = r#"
+        JMP    bomb01
+i       FOR    2
+bomb&i  DAT    #i, #CURLINE
+        ROF
+        DAT    #CORESIZE-1, #MAXPROCESSES
+"#;
+
+    let env = Environment {
+      core_size: 100,
+      max_processes: 64,
+      ..Environment::default()
+    };
+    let warrior = parse_program_with_environment(program, &env).unwrap();
+    assert_eq!(
+      warrior.instructions,
+      vec![
+        Instruction::new(Jmp, B, Direct, 1, Direct, 0),
+        Instruction::new(Dat, F, Immediate, 1, Immediate, 1),
+        Instruction::new(Dat, F, Immediate, 2, Immediate, 2),
+        Instruction::new(Dat, F, Immediate, 99, Immediate, 64),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_empty_core() {
+    let env = Environment {
+      core_size: 0,
+      ..Environment::default()
+    };
+    let error = parse_program_with_environment("DAT 0, 1", &env).unwrap_err();
+    assert_eq!(error.kind, ParseErrorKind::EmptyCore);
+    assert_eq!(error.line, 1);
+
+    let error = parse_program_with_environment("", &env).unwrap_err();
+    assert_eq!(error.kind, ParseErrorKind::EmptyCore);
+  }
+
+  #[test]
+  fn test_syntax_errors() {
+    let cases = [
+      ("MOV 0, ?", 8, "?", "an expression"),
+      ("MOV 0,", 7, "", "an expression"),
+      ("MOV.Q 0, 1", 5, "Q", "a modifier (A, B, AB, BA, F, X or I)"),
+      ("DAT (1+2, 0", 9, ",", "`)`"),
+      ("DAT 1 +", 8, "", "an expression"),
+      ("DAT 0, 1 2", 10, "2", "the end of the line"),
+      ("start 1", 7, "1", "an opcode or pseudo-op"),
+      ("x EQU", 6, "", "an expression"),
+    ];
+
+    for (input, column, token, expected) in cases.iter() {
+      let error = parse_program(input).unwrap_err();
+      assert_eq!(error.kind, ParseErrorKind::Syntax, "{}", input);
+      assert_eq!(error.column, *column, "{}", input);
+      assert_eq!(error.token, *token, "{}", input);
+      assert_eq!(error.expected, Some(*expected), "{}", input);
+    }
+  }
+
+  #[test]
+  fn test_error_snippet_shows_comments() {
+    let error = parse_program("JMP 0\n  JMP bomb ; missing").unwrap_err();
+    assert_eq!(
+      error.to_string(),
+      "undefined label `bomb` on line 2, column 7
+  |
+2 |   JMP bomb ; missing
+  |       ^^^^"
+    );
+  }
+
+  #[test]
+  fn test_collect_all_errors() {
+    let program = r#"
+        JMP    nowhere
+        MOV    0, ?
+a       DAT    0
+a       DAT    1/0
+        ORG    elsewhere
+"#;
+
+    let errors = parse_program_collecting_errors(program, &Environment::default()).unwrap_err();
+    assert_eq!(
+      errors
+        .iter()
+        .map(|error| (error.kind.clone(), error.line))
+        .collect::<Vec<_>>(),
+      vec![
+        (ParseErrorKind::UndefinedLabel("nowhere".to_string()), 2),
+        (ParseErrorKind::Syntax, 3),
+        (ParseErrorKind::DuplicateLabel("a".to_string()), 5),
+        (ParseErrorKind::DivisionByZero, 5),
+        (ParseErrorKind::UndefinedLabel("elsewhere".to_string()), 6),
+      ]
+    );
+  }
 }
@@ -0,0 +1,371 @@
+//! Expansion of `FOR`/`ROF` blocks
+use {
+  super::{
+    expr::parse_identifier, parse_line, Environment, Line, ParseError, ParseErrorKind, Statement,
+    Symbols,
+  },
+  nom::types::CompleteStr,
+};
+
+/// A line of source code along with its line number
+pub type SourceLine = (usize, String);
+
+/// Expand every `FOR`/`ROF` block in `lines`. Lines following an `END` are
+/// dropped, as are blocks that can't be expanded, whose errors are added to
+/// `errors`
+///
+/// The last label of a `FOR` line names the block's counter, which counts up
+/// from 1. Inside of the block `&counter` is replaced with the zero padded
+/// counter, e.g. `01`, and `counter` with the counter's value
+///
+/// Expansion stops at a block that would make the program longer than the
+/// maximum length, or whose iterations that output no instructions add up to
+/// more than it
+pub fn expand(
+  lines: Vec<SourceLine>,
+  env: &Environment,
+  errors: &mut Vec<ParseError>,
+) -> Vec<SourceLine> {
+  // block counts may refer to constants defined before the block
+  let mut constants = Symbols::new(env);
+  let mut output = vec![];
+  let mut budget = Budget::default();
+  expand_into(
+    lines,
+    &mut constants,
+    &mut output,
+    &mut budget,
+    None,
+    errors,
+  );
+  output
+}
+
+/// Work done expanding blocks, which is bounded by the maximum length
+#[derive(Debug, Default)]
+struct Budget {
+  /// Instructions output so far
+  instructions: usize,
+  /// Iterations of blocks that output no instructions, which nested blocks
+  /// could otherwise repeat without bound
+  empty_iterations: usize,
+}
+
+/// Expand `lines` into `output`, counting the work done in `budget`
+///
+/// # Params
+/// * `block`: the `FOR` line of the innermost block that `lines` are repeated
+///   by, where running out of budget is reported
+///
+/// # Returns
+/// `true` if an `END` was reached or expansion was stopped
+fn expand_into(
+  lines: Vec<SourceLine>,
+  constants: &mut Symbols,
+  output: &mut Vec<SourceLine>,
+  budget: &mut Budget,
+  block: Option<&SourceLine>,
+  errors: &mut Vec<ParseError>,
+) -> bool {
+  let max_length = constants.env.max_length;
+  let too_long = |&(line, ref text): &SourceLine| {
+    ParseError::at_token(
+      ParseErrorKind::ForTooLong(max_length),
+      line,
+      text,
+      "FOR",
+      None,
+    )
+  };
+
+  let mut lines = lines.into_iter();
+  while let Some((line, text)) = lines.next() {
+    match parse_line(CompleteStr(&text)).map(|(_, parsed)| parsed) {
+      Ok(Line {
+        mut labels,
+        statement: Some(Statement::For(count)),
+      }) => {
+        let counter = labels.pop();
+        // any other labels refer to the first instruction of the block
+        if !labels.is_empty() {
+          output.push((line, labels.join(" ")));
+        }
+
+        let block = match take_block(&mut lines) {
+          Some(block) => block,
+          None => {
+            errors.push(ParseError::at_token(
+              ParseErrorKind::UnterminatedFor,
+              line,
+              &text,
+              "FOR",
+              None,
+            ));
+            return false;
+          }
+        };
+        let count = match constants.eval(&count, 0) {
+          Ok(count) => count,
+          Err(e) => {
+            errors.push(ParseError::from_eval(e, line, &text));
+            continue;
+          }
+        };
+
+        let for_line = (line, text);
+        for i in 1..=count {
+          if budget.empty_iterations >= max_length {
+            errors.push(too_long(&for_line));
+            return true;
+          }
+          let block = block
+            .iter()
+            .map(|(line, text)| match counter {
+              Some(ref counter) => (*line, substitute(text, counter, i)),
+              None => (*line, text.clone()),
+            })
+            .collect();
+
+          let instructions = budget.instructions;
+          if expand_into(block, constants, output, budget, Some(&for_line), errors) {
+            return true;
+          }
+          if budget.instructions == instructions {
+            budget.empty_iterations += 1;
+          }
+        }
+      }
+      Ok(Line {
+        statement: Some(Statement::Rof),
+        ..
+      }) => errors.push(ParseError::at_token(
+        ParseErrorKind::UnmatchedRof,
+        line,
+        &text,
+        "ROF",
+        None,
+      )),
+      Ok(Line {
+        labels,
+        statement: Some(Statement::Equ(expr)),
+      }) => {
+        // duplicates are reported when the program is assembled
+        for label in labels {
+          let _ = constants.define_constant(label, expr.clone());
+        }
+        output.push((line, text));
+      }
+      Ok(Line {
+        statement: Some(Statement::End(_)),
+        ..
+      }) => {
+        output.push((line, text));
+        return true;
+      }
+      Ok(Line {
+        statement: Some(Statement::Instruction(_)),
+        ..
+      }) => {
+        if let Some(for_line) = block {
+          if budget.instructions >= max_length {
+            errors.push(too_long(for_line));
+            return true;
+          }
+        }
+        budget.instructions += 1;
+        output.push((line, text));
+      }
+      _ => output.push((line, text)),
+    }
+  }
+
+  false
+}
+
+/// Take the lines of a block up to, but not including, its `ROF`
+///
+/// # Returns
+/// `None` if the block has no `ROF`
+fn take_block<I>(lines: &mut I) -> Option<Vec<SourceLine>>
+where
+  I: Iterator<Item = SourceLine>,
+{
+  let mut depth = 0;
+  let mut block = vec![];
+
+  for (line, text) in lines {
+    match parse_line(CompleteStr(&text)).map(|(_, parsed)| parsed.statement) {
+      Ok(Some(Statement::For(_))) => depth += 1,
+      Ok(Some(Statement::Rof)) if depth == 0 => return Some(block),
+      Ok(Some(Statement::Rof)) => depth -= 1,
+      _ => {}
+    }
+    block.push((line, text));
+  }
+
+  None
+}
+
+/// Replace `&counter` with the zero padded `value` and `counter` with `value`
+fn substitute(text: &str, counter: &str, value: i64) -> String {
+  let mut output = String::with_capacity(text.len());
+  let mut rest = text;
+
+  while let Some(c) = rest.chars().next() {
+    if c == '&' {
+      match parse_identifier(CompleteStr(&rest[1..])) {
+        Ok((remaining, identifier)) if identifier.0 == counter => {
+          output.push_str(&format!("{:02}", value));
+          rest = remaining.0;
+          continue;
+        }
+        _ => {}
+      }
+    }
+
+    match parse_identifier(CompleteStr(rest)) {
+      Ok((remaining, identifier)) => {
+        if identifier.0 == counter {
+          output.push_str(&value.to_string());
+        } else {
+          output.push_str(identifier.0);
+        }
+        rest = remaining.0;
+      }
+      Err(_) => {
+        output.push(c);
+        rest = &rest[c.len_utf8()..];
+      }
+    }
+  }
+
+  output
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn lines(s: &str) -> Vec<SourceLine> {
+    s.lines()
+      .enumerate()
+      .map(|(i, text)| (i + 1, text.to_string()))
+      .collect()
+  }
+
+  fn texts(lines: Vec<SourceLine>) -> Vec<String> {
+    lines.into_iter().map(|(_, text)| text).collect()
+  }
+
+  #[test]
+  fn test_substitute() {
+    let cases = [
+      ("bomb&i DAT #i*2", "bomb03 DAT #3*2"),
+      ("DAT i, index", "DAT 3, index"),
+      ("x&j &i", "x&j 03"),
+    ];
+    for (text, expected) in cases.iter() {
+      assert_eq!(substitute(text, "i", 3), *expected);
+    }
+  }
+
+  #[test]
+  fn test_expand() {
+    let source = "n EQU 2
+i FOR n
+  j FOR 2
+    DAT i, j
+  ROF
+ROF
+END
+junk";
+    let expanded = expand(lines(source), &Environment::default(), &mut vec![]);
+    assert_eq!(
+      expanded.iter().map(|&(line, _)| line).collect::<Vec<_>>(),
+      vec![1, 4, 4, 4, 4, 7]
+    );
+    assert_eq!(
+      texts(expanded),
+      vec![
+        "n EQU 2",
+        "    DAT 1, 1",
+        "    DAT 1, 2",
+        "    DAT 2, 1",
+        "    DAT 2, 2",
+        "END",
+      ]
+    );
+  }
+
+  #[test]
+  fn test_expand_with_predefined() {
+    let env = Environment {
+      core_size: 3,
+      ..Environment::default()
+    };
+    let expanded = expand(lines("FOR CORESIZE\nNOP\nROF"), &env, &mut vec![]);
+    assert_eq!(texts(expanded), vec!["NOP", "NOP", "NOP"]);
+
+    let expanded = expand(lines("start FOR 0\nNOP\nROF"), &env, &mut vec![]);
+    assert!(expanded.is_empty());
+  }
+
+  #[test]
+  fn test_expand_errors() {
+    let env = Environment::default();
+    let mut errors = vec![];
+    let expanded = expand(lines("NOP\nROF\nFOR 2\nNOP"), &env, &mut errors);
+    assert_eq!(texts(expanded), vec!["NOP"]);
+    assert_eq!(
+      errors
+        .iter()
+        .map(|error| (error.kind.clone(), error.line, error.column))
+        .collect::<Vec<_>>(),
+      vec![
+        (ParseErrorKind::UnmatchedRof, 2, 1),
+        (ParseErrorKind::UnterminatedFor, 3, 1),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_expand_too_long() {
+    let env = Environment {
+      max_length: 4,
+      ..Environment::default()
+    };
+    let kinds = |source| {
+      let mut errors = vec![];
+      expand(lines(source), &env, &mut errors);
+      errors
+        .into_iter()
+        .map(|error| (error.kind, error.line))
+        .collect::<Vec<_>>()
+    };
+
+    assert_eq!(kinds("FOR 4\nNOP\nROF"), vec![]);
+    assert_eq!(
+      kinds("NOP\nFOR 2000000\nNOP\nROF"),
+      vec![(ParseErrorKind::ForTooLong(4), 2)]
+    );
+    // nested blocks that each repeat a few times
+    assert_eq!(
+      kinds("FOR 3\nFOR 3\nDAT 0\nROF\nROF"),
+      vec![(ParseErrorKind::ForTooLong(4), 2)]
+    );
+    // a single block can't go past the limit
+    let mut errors = vec![];
+    let output = expand(lines("DAT 0\nFOR 2\nDAT 0\nDAT 0\nROF"), &env, &mut errors);
+    assert_eq!(errors.len(), 1);
+    assert!(output.len() <= 4);
+    // nested blocks that output nothing
+    assert_eq!(
+      kinds("FOR 1000\nFOR 1000\nFOR 1000\nROF\nROF\nROF"),
+      vec![(ParseErrorKind::ForTooLong(4), 3)]
+    );
+    assert_eq!(
+      kinds("FOR 1000\nFOR 1000\nx EQU 1\nROF\nROF"),
+      vec![(ParseErrorKind::ForTooLong(4), 2)]
+    );
+  }
+}
@@ -81,6 +81,22 @@ impl Instruction {
   }
 }
 
+/// An assembled program along with the metadata from its header comments
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Warrior {
+  pub instructions: Vec<Instruction>,
+  /// Offset of the first instruction to execute
+  pub start: Address,
+  /// Dialect from a `;redcode` line, e.g. `94` for `;redcode-94`
+  pub redcode: Option<String>,
+  pub name: Option<String>,
+  pub author: Option<String>,
+  pub version: Option<String>,
+  /// Lines of all `;strategy` comments
+  pub strategy: Option<String>,
+  pub date: Option<String>,
+}
+
 /// An instruction field containing the mode and opcode
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct OpField {
@@ -95,9 +111,10 @@ impl fmt::Display for OpField {
 }
 
 /// An opcode
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum OpCode {
   /// Data
+  #[default]
   Dat,
   /// Move
   Mov,
@@ -137,12 +154,6 @@ pub enum OpCode {
   Nop,
 }
 
-impl Default for OpCode {
-  fn default() -> Self {
-    OpCode::Dat
-  }
-}
-
 impl fmt::Display for OpCode {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     let s = match *self {
@@ -172,7 +183,7 @@ impl fmt::Display for OpCode {
 }
 
 /// A opcode modifier
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum OpMode {
   // A -> A
   A,
@@ -187,15 +198,10 @@ pub enum OpMode {
   // (A, B) -> (B, A)
   X,
   // All -> All
+  #[default]
   I,
 }
 
-impl Default for OpMode {
-  fn default() -> Self {
-    OpMode::I
-  }
-}
-
 impl fmt::Display for OpMode {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     let s = match *self {
@@ -226,9 +232,10 @@ impl fmt::Display for Field {
 }
 
 /// A `Field`'s adressing mode
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum AddressingMode {
   Immediate,
+  #[default]
   Direct,
   AIndirect(IncrementMode),
   BIndirect(IncrementMode),
@@ -236,10 +243,7 @@ pub enum AddressingMode {
 
 impl AddressingMode {
   pub fn is_immediate(&self) -> bool {
-    match *self {
-      Immediate => true,
-      _ => false,
-    }
+    matches!(*self, Immediate)
   }
 }
 
@@ -260,12 +264,6 @@ impl fmt::Display for AddressingMode {
   }
 }
 
-impl Default for AddressingMode {
-  fn default() -> Self {
-    AddressingMode::Direct
-  }
-}
-
 /// A `AddressingMode`s increment mode
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum IncrementMode {
@@ -0,0 +1,551 @@
+//! Replays, which record everything needed to fight a battle again exactly
+//! as it was fought
+//!
+//! A replay is saved as text, one setting per line:
+//!
+//! ```text
+//! size 8000
+//! max_cycles 80000
+//! seed 42
+//! player 0 0
+//! pspace 7999 0 0 0 0 0 0 0
+//! warrior 2
+//! ;name Imp
+//! MOV.I $0, $1
+//! result 80000 tie 0 1
+//! ```
+//!
+//! `warrior` is followed by the number of lines of the warrior's source, and
+//! `result` by the cycle the battle ended in
+//!
+//! `seed` is the seed of the RNG that chose the load addresses, if any were
+//! random. It is only recorded for reference: the addresses themselves are
+//! saved with each `player`, and those are what the battle is fought with, so
+//! a replay doesn't depend on how many numbers were drawn from the RNG before
+//! the battle, e.g. in earlier rounds of a `Match`, or on the RNG's algorithm
+use {
+  failure::Fail,
+  game::{BattleOutcome, Game, GameError, Pin},
+  parse::{parse_program_with_environment, Environment, ParseError},
+  rand::Rng,
+  redcode::{Address, Warrior},
+  simulation::{MarsBuilder, MarsError},
+  std::{cell::RefCell, fmt, fs, io, path::Path, rc::Rc},
+};
+
+#[derive(Debug, Fail)]
+pub enum ReplayError {
+  #[fail(display = "failed to access {}: {}", path, cause)]
+  Io {
+    path: String,
+    #[cause]
+    cause: io::Error,
+  },
+  #[fail(display = "failed to parse the warrior on line {}: {}", line, cause)]
+  Parse { line: usize, cause: Box<ParseError> },
+  #[fail(display = "invalid replay on line {}", line)]
+  Corrupt { line: usize },
+  #[fail(display = "{}", _0)]
+  Mars(#[cause] MarsError),
+  #[fail(display = "{}", _0)]
+  Game(#[cause] GameError),
+  #[fail(
+    display = "the battle ended with {:?} in cycle {} instead of {:?} in cycle {}",
+    outcome, cycle, expected, expected_cycle
+  )]
+  Diverged {
+    outcome: BattleOutcome,
+    cycle: usize,
+    expected: BattleOutcome,
+    expected_cycle: usize,
+  },
+}
+
+impl From<MarsError> for ReplayError {
+  fn from(error: MarsError) -> Self {
+    ReplayError::Mars(error)
+  }
+}
+
+impl From<GameError> for ReplayError {
+  fn from(error: GameError) -> Self {
+    ReplayError::Game(error)
+  }
+}
+
+/// A warrior in a replay
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Player {
+  pub pin: Pin,
+  pub address: Address,
+  pub warrior: Warrior,
+  /// Contents of the warrior's P-space when it was loaded
+  pub pspace: Vec<Address>,
+}
+
+/// A battle that can be saved and fought again
+#[derive(Debug, Clone)]
+pub struct Replay {
+  builder: MarsBuilder,
+  /// Seed of the RNG that chose the load addresses
+  seed: Option<u64>,
+  players: Vec<Player>,
+  /// The outcome of the battle and the cycle it ended in
+  result: Option<(BattleOutcome, usize)>,
+}
+
+impl Replay {
+  /// Create a replay of a battle in a `Mars` built by `builder`
+  pub fn new(builder: MarsBuilder) -> Result<Self, MarsError> {
+    builder.build()?;
+
+    Ok(Replay {
+      builder,
+      seed: None,
+      players: vec![],
+      result: None,
+    })
+  }
+
+  /// Add a warrior with a pin that is loaded at `address`
+  ///
+  /// # Errors
+  /// Fails if the pin is taken or the warrior is longer than the maximum
+  /// length
+  pub fn add_warrior(
+    &mut self,
+    warrior: Warrior,
+    address: Address,
+    pin: Pin,
+  ) -> Result<Pin, GameError> {
+    let pspace = self.build_game().mars().new_pspace().borrow().clone();
+    self.add_player(Player {
+      pin,
+      address,
+      warrior,
+      pspace,
+    })
+  }
+
+  /// Add a warrior that is loaded at a random address, see
+  /// `Game::random_address`. Only the address is saved, not `rng`, see
+  /// `set_seed`
+  pub fn add_warrior_rand<R>(&mut self, warrior: Warrior, rng: &mut R) -> Result<Pin, GameError>
+  where
+    R: Rng,
+  {
+    let pin = (0..)
+      .find(|&pin| self.players.iter().all(|player| player.pin != pin))
+      .expect("ran out of pins");
+    let address = self
+      .build_game()
+      .random_address(warrior.instructions.len(), rng)?;
+    self.add_warrior(warrior, address, pin)
+  }
+
+  /// Add a player, keeping the contents of its P-space, e.g. from a previous
+  /// round of a `Match`
+  ///
+  /// # Errors
+  /// Fails if the pin is taken or the warrior is longer than the maximum
+  /// length
+  pub fn add_player(&mut self, player: Player) -> Result<Pin, GameError> {
+    if self.players.iter().any(|other| other.pin == player.pin) {
+      return Err(GameError::PinConflict { pin: player.pin });
+    }
+    // loading the warrior checks its length
+    let mut game = self.build_game();
+    let pspace = Rc::new(RefCell::new(player.pspace.clone()));
+    game.add_warrior_with_pspace(&player.warrior, player.address, player.pin, pspace)?;
+
+    let pin = player.pin;
+    self.players.push(player);
+    self.result = None;
+    Ok(pin)
+  }
+
+  /// Return a game with every warrior loaded, ready to fight the battle
+  pub fn game(&self) -> Game {
+    self.build_game()
+  }
+
+  fn build_game(&self) -> Game {
+    let mars = self
+      .builder
+      .build()
+      .expect("the builder was validated when the replay was created");
+    let mut game = Game::new(mars);
+    for player in &self.players {
+      let pspace = Rc::new(RefCell::new(player.pspace.clone()));
+      game
+        .add_warrior_with_pspace(&player.warrior, player.address, player.pin, pspace)
+        .expect("players were checked when they were added");
+    }
+    game
+  }
+
+  /// Fight the battle and record its result
+  ///
+  /// # Errors
+  /// Fails if a result was already recorded and the battle ends differently
+  pub fn run(&mut self) -> Result<BattleOutcome, ReplayError> {
+    let mut game = self.build_game();
+    let outcome = game.run_to_completion();
+    let cycle = game.mars().cycle();
+
+    if let Some((ref expected, expected_cycle)) = self.result {
+      if *expected != outcome || expected_cycle != cycle {
+        return Err(ReplayError::Diverged {
+          outcome,
+          cycle,
+          expected: expected.clone(),
+          expected_cycle,
+        });
+      }
+    }
+
+    self.result = Some((outcome.clone(), cycle));
+    Ok(outcome)
+  }
+
+  pub fn builder(&self) -> MarsBuilder {
+    self.builder
+  }
+
+  /// Return the seed of the RNG that chose the load addresses, if it was
+  /// recorded
+  pub fn seed(&self) -> Option<u64> {
+    self.seed
+  }
+
+  /// Record the seed of the RNG that chose the load addresses. The battle is
+  /// still fought with the addresses the players were added with
+  pub fn set_seed(&mut self, seed: u64) {
+    self.seed = Some(seed);
+  }
+
+  pub fn players(&self) -> &[Player] {
+    &self.players
+  }
+
+  /// Return the recorded outcome of the battle and the cycle it ended in
+  pub fn result(&self) -> Option<(&BattleOutcome, usize)> {
+    self
+      .result
+      .as_ref()
+      .map(|&(ref outcome, cycle)| (outcome, cycle))
+  }
+
+  /// Record the outcome of a battle that was fought in a `Game` and the cycle
+  /// it ended in
+  pub(crate) fn set_result(&mut self, outcome: BattleOutcome, cycle: usize) {
+    self.result = Some((outcome, cycle));
+  }
+
+  /// Write the replay to a file
+  pub fn save(&self, path: &Path) -> Result<(), ReplayError> {
+    fs::write(path, self.to_string()).map_err(|cause| ReplayError::Io {
+      path: path.display().to_string(),
+      cause,
+    })
+  }
+
+  /// Read a replay from a file
+  pub fn load(path: &Path) -> Result<Self, ReplayError> {
+    let source = fs::read_to_string(path).map_err(|cause| ReplayError::Io {
+      path: path.display().to_string(),
+      cause,
+    })?;
+    Replay::parse(&source)
+  }
+
+  /// Parse a replay saved with `Display`
+  pub fn parse(source: &str) -> Result<Self, ReplayError> {
+    let mut size = None;
+    // settings other than the size, applied once the size is known
+    let mut settings: Vec<(&str, usize)> = vec![];
+    let mut seed = None;
+    let mut players: Vec<Player> = vec![];
+    // line the source of each warrior starts on, along with the source
+    let mut warriors: Vec<(usize, String)> = vec![];
+    let mut result = None;
+
+    let mut lines = source.lines().enumerate().map(|(i, line)| (i + 1, line));
+    while let Some((line_number, line)) = lines.next() {
+      let corrupt = || ReplayError::Corrupt { line: line_number };
+      let words: Vec<&str> = line.split_whitespace().collect();
+      if words.is_empty() {
+        continue;
+      }
+      if words[0] == "result" {
+        result = Some(parse_result(&words[1..]).ok_or_else(corrupt)?);
+        continue;
+      }
+      let numbers = words[1..]
+        .iter()
+        .map(|word| word.parse::<u64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| corrupt())?;
+
+      match (words[0], numbers.as_slice()) {
+        ("size", &[value]) => size = Some(value as usize),
+        (setting, &[value]) if is_setting(setting) => settings.push((setting, value as usize)),
+        ("seed", &[value]) => seed = Some(value),
+        ("player", &[pin, address]) => {
+          if players.len() != warriors.len() {
+            return Err(corrupt());
+          }
+          players.push(Player {
+            pin: pin as Pin,
+            address: address as Address,
+            warrior: Warrior::default(),
+            pspace: vec![],
+          });
+        }
+        ("pspace", values) => {
+          let player = players.last_mut().ok_or_else(corrupt)?;
+          player.pspace = values.iter().map(|&value| value as Address).collect();
+        }
+        ("warrior", &[count]) => {
+          if players.len() != warriors.len() + 1 {
+            return Err(corrupt());
+          }
+          let mut source = String::new();
+          for _ in 0..count {
+            let (_, line) = lines.next().ok_or_else(corrupt)?;
+            source.push_str(line);
+            source.push('\n');
+          }
+          warriors.push((line_number + 1, source));
+        }
+        _ => return Err(corrupt()),
+      }
+    }
+
+    let end = ReplayError::Corrupt {
+      line: source.lines().count(),
+    };
+    if players.len() != warriors.len() {
+      return Err(end);
+    }
+    let mut builder = MarsBuilder::new(size.ok_or(end)?);
+    for (setting, value) in settings {
+      builder = builder
+        .setting(setting, value)
+        .expect("only settings were collected");
+    }
+    let mut replay = Replay::new(builder)?;
+    replay.seed = seed;
+
+    let mut env = Environment::from(&builder);
+    env.warriors = players.len();
+    let p_space_size = builder.build()?.p_space_size();
+    for (mut player, (line, source)) in players.into_iter().zip(warriors) {
+      player.warrior =
+        parse_program_with_environment(&source, &env).map_err(|cause| ReplayError::Parse {
+          line,
+          cause: Box::new(cause),
+        })?;
+      if player.pspace.len() != p_space_size {
+        return Err(ReplayError::Corrupt { line: line - 1 });
+      }
+      replay.add_player(player)?;
+    }
+    replay.result = result;
+    Ok(replay)
+  }
+}
+
+impl fmt::Display for Replay {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for &(setting, value) in &self.builder.settings() {
+      writeln!(f, "{} {}", setting, value)?;
+    }
+    if let Some(seed) = self.seed {
+      writeln!(f, "seed {}", seed)?;
+    }
+
+    for player in &self.players {
+      writeln!(f, "player {} {}", player.pin, player.address)?;
+      let pspace: Vec<String> = player.pspace.iter().map(Address::to_string).collect();
+      writeln!(f, "pspace {}", pspace.join(" "))?;
+      let source = warrior_source(&player.warrior);
+      writeln!(f, "warrior {}", source.len())?;
+      for line in source {
+        writeln!(f, "{}", line)?;
+      }
+    }
+
+    if let Some((ref outcome, cycle)) = self.result {
+      let pins = |pins: &[Pin]| {
+        pins
+          .iter()
+          .map(|pin| format!(" {}", pin))
+          .collect::<String>()
+      };
+      match outcome {
+        BattleOutcome::Win(pin) => writeln!(f, "result {} win {}", cycle, pin)?,
+        BattleOutcome::Tie(tied) => writeln!(f, "result {} tie{}", cycle, pins(tied))?,
+        BattleOutcome::AllDead => writeln!(f, "result {} dead", cycle)?,
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Return true if `name` is one of the `MarsBuilder::settings`
+fn is_setting(name: &str) -> bool {
+  MarsBuilder::new(1)
+    .settings()
+    .iter()
+    .any(|&(setting, _)| setting == name)
+}
+
+/// Parse the words after `result`
+fn parse_result(words: &[&str]) -> Option<(BattleOutcome, usize)> {
+  let cycle = words.first()?.parse().ok()?;
+  let pins = words
+    .get(2..)
+    .unwrap_or(&[])
+    .iter()
+    .map(|word| word.parse::<Pin>().ok())
+    .collect::<Option<Vec<_>>>()?;
+
+  let outcome = match (words.get(1), pins.as_slice()) {
+    (Some(&"win"), &[pin]) => BattleOutcome::Win(pin),
+    (Some(&"tie"), _) => BattleOutcome::Tie(pins),
+    (Some(&"dead"), &[]) => BattleOutcome::AllDead,
+    _ => return None,
+  };
+  Some((outcome, cycle))
+}
+
+/// Return the lines of source that parse back into `warrior`
+fn warrior_source(warrior: &Warrior) -> Vec<String> {
+  let mut lines = vec![];
+  let headers = [
+    ("redcode", &warrior.redcode),
+    ("name", &warrior.name),
+    ("author", &warrior.author),
+    ("version", &warrior.version),
+    ("date", &warrior.date),
+  ];
+  for &(keyword, value) in headers.iter() {
+    if let Some(value) = value {
+      lines.push(format!(";{} {}", keyword, value));
+    }
+  }
+  if let Some(ref strategy) = warrior.strategy {
+    lines.extend(strategy.lines().map(|line| format!(";strategy {}", line)));
+  }
+
+  lines.push(format!("ORG {}", warrior.start));
+  for instruction in &warrior.instructions {
+    lines.push(format!(
+      "{} {}, {}",
+      instruction.op, instruction.a, instruction.b
+    ));
+  }
+  lines
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use rand::{rngs::StdRng, SeedableRng};
+  use std::env;
+
+  const IMP: &str = ";name Imp\n;author A. K. Dewdney\nMOV.I 0, 1";
+  const DWARF: &str = ";name Dwarf\n;strategy bombs\n;strategy the core\nstart ADD #4, 3\n\
+                       MOV 2, @2\nJMP -2\nDAT #0, #0\nEND start";
+
+  fn replay() -> Replay {
+    let builder = MarsBuilder::new(800)
+      .max_cycles(2000)
+      .max_length(50)
+      .min_distance(50);
+    let env = Environment::from(&builder);
+    let parse = |source| parse_program_with_environment(source, &env).unwrap();
+    let mut replay = Replay::new(builder).unwrap();
+    replay.add_warrior(parse(IMP), 100, 3).unwrap();
+    replay
+      .add_warrior_rand(parse(DWARF), &mut StdRng::seed_from_u64(7))
+      .unwrap();
+    replay.set_seed(7);
+    replay
+  }
+
+  #[test]
+  fn round_trip() {
+    let mut replay = replay();
+    replay.run().unwrap();
+
+    let parsed = Replay::parse(&replay.to_string()).unwrap();
+    assert_eq!(parsed.builder(), replay.builder());
+    assert_eq!(parsed.seed(), Some(7));
+    assert_eq!(parsed.players(), replay.players());
+    assert_eq!(parsed.result(), replay.result());
+    assert_eq!(parsed.to_string(), replay.to_string());
+
+    let path = env::temp_dir().join(format!("libcw-replay-{}", std::process::id()));
+    replay.save(&path).unwrap();
+    let loaded = Replay::load(&path).unwrap();
+    let _ = fs::remove_file(&path);
+    assert_eq!(loaded.players(), replay.players());
+  }
+
+  #[test]
+  fn rerun_matches() {
+    let mut replay = replay();
+    let outcome = replay.run().unwrap();
+    let (_, cycle) = replay.result().unwrap();
+
+    let mut parsed = Replay::parse(&replay.to_string()).unwrap();
+    assert_eq!(parsed.run().unwrap(), outcome);
+    assert_eq!(parsed.result().unwrap().1, cycle);
+  }
+
+  #[test]
+  fn diverging_result_is_reported() {
+    let mut replay = replay();
+    replay.run().unwrap();
+    let source = replay
+      .to_string()
+      .lines()
+      .map(|line| {
+        if line.starts_with("result") {
+          "result 1 dead".to_owned()
+        } else {
+          line.to_owned()
+        }
+      })
+      .collect::<Vec<_>>()
+      .join("\n");
+
+    let mut parsed = Replay::parse(&source).unwrap();
+    match parsed.run() {
+      Err(ReplayError::Diverged { expected, .. }) => assert_eq!(expected, BattleOutcome::AllDead),
+      other => panic!("expected a diverged replay, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn corrupt_replays() {
+    assert!(matches!(
+      Replay::parse("size 800\nsizes 8"),
+      Err(ReplayError::Corrupt { line: 2 })
+    ));
+    assert!(matches!(
+      Replay::parse("max_cycles 1"),
+      Err(ReplayError::Corrupt { line: 1 })
+    ));
+    assert!(matches!(
+      Replay::parse("size 800\nplayer 0 0\nwarrior 3\nMOV 0, 1"),
+      Err(ReplayError::Corrupt { line: 3 })
+    ));
+    assert!(matches!(
+      Replay::parse("size 800\nplayer 0 0\npspace 799\nwarrior 1\nMOV 0, 1"),
+      Err(ReplayError::Corrupt { line: 4 })
+    ));
+  }
+}
@@ -0,0 +1,91 @@
+//! What happens when a `Mars` steps
+use {
+  super::Pid,
+  redcode::{Address, Instruction},
+};
+
+/// Whether P-space was read from or written to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PSpaceAccessKind {
+  Load,
+  Store,
+}
+
+/// Something that happened during a step. Events are emitted in the order
+/// they are listed here
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+  /// The process is about to execute the instruction at `pc` in `cycle`,
+  /// with operands that resolved to `a_pointer` and `b_pointer`. The B
+  /// operand is given as folded by the write limit
+  InstructionExecuted {
+    pid: Pid,
+    cycle: usize,
+    pc: Address,
+    instruction: Instruction,
+    a_pointer: Address,
+    b_pointer: Address,
+  },
+  /// The contents of the cell at `address` changed from `old` to `new`
+  MemoryWritten {
+    pid: Pid,
+    address: Address,
+    old: Instruction,
+    new: Instruction,
+  },
+  /// The process loaded from or stored to its P-space. `index` is folded
+  /// into the P-space
+  PSpaceAccess {
+    pid: Pid,
+    kind: PSpaceAccessKind,
+    index: Address,
+    value: Address,
+  },
+  /// The process queued a new thread at `address`
+  ThreadSplit { pid: Pid, address: Address },
+  /// The thread that executed the instruction at `pc` died
+  ThreadKilled { pid: Pid, pc: Address },
+  /// The process died with its last thread
+  ProcessKilled { pid: Pid },
+}
+
+/// Receives the events of every step, see `Mars::step_with`
+pub trait Observer {
+  fn observe(&mut self, event: &Event);
+}
+
+/// Ignores every event
+impl Observer for () {
+  fn observe(&mut self, _: &Event) {}
+}
+
+/// Collects every event
+impl Observer for Vec<Event> {
+  fn observe(&mut self, event: &Event) {
+    self.push(*event);
+  }
+}
+
+/// The result of a step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepOutcome {
+  /// The process that executed
+  pub pid: Pid,
+  /// Address of the executed instruction
+  pub pc: Address,
+  /// The instruction as it was fetched, before it was executed
+  pub instruction: Instruction,
+  /// Where the thread continues, or `None` if it died
+  pub next_pc: Option<Address>,
+  /// Where the new thread of a `SPL` starts, if it was queued
+  pub split: Option<Address>,
+  /// Whether the process died with its last thread
+  pub process_killed: bool,
+}
+
+impl StepOutcome {
+  /// Return true if the thread that executed died
+  pub fn thread_killed(&self) -> bool {
+    self.next_pc.is_none()
+  }
+}
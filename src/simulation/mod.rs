@@ -1,16 +1,37 @@
 //! Your one-stop shop for everything Core Wars
+mod event;
+mod snapshot;
+mod trace;
+mod tracking;
+mod undo;
+
+pub use self::event::{Event, Observer, PSpaceAccessKind, StepOutcome};
+pub use self::snapshot::Snapshot;
+pub use self::trace::{TraceFormat, Tracer};
+pub use self::tracking::{Access, CellHistory};
+
+use self::undo::UndoEntry;
+
 use {
-  itertools::assert_equal,
+  failure::Fail,
+  parse::Environment,
   redcode::{
-    self, Address, AddressingMode, AddressingMode::*, Field, IncrementMode, Instruction, OpCode,
-    OpCode::*, OpField, OpMode, OpMode::*,
+    Address, AddressingMode, AddressingMode::*, Field, IncrementMode, Instruction, OpCode::*,
+    Warrior,
   },
+  std::cell::{Ref, RefCell},
   std::collections::VecDeque,
   std::rc::Rc,
 };
 
-const MARS_DEFAULT_SIZE: usize = 8000;
-const MARS_DEFAULT_P_SPACE_SIZE: usize = 8;
+pub const MARS_DEFAULT_SIZE: usize = 8000;
+pub const MARS_DEFAULT_P_SPACE_SIZE: usize = 8;
+pub const MARS_DEFAULT_MAX_PROCESSES: usize = 8000;
+pub const MARS_DEFAULT_MAX_CYCLES: usize = 80000;
+pub const MARS_DEFAULT_MAX_LENGTH: usize = 100;
+pub const MARS_DEFAULT_MIN_DISTANCE: usize = 100;
+/// Number of steps a reversible Mars remembers by default
+pub const MARS_DEFAULT_UNDO_LIMIT: usize = 100_000;
 
 /// A process id
 pub type Pid = usize;
@@ -18,19 +39,35 @@ pub type Pid = usize;
 /// A collection on queued threads
 pub type Threads = VecDeque<Address>;
 
-/// Process storage
-pub type PSpace = Rc<Vec<Address>>;
+/// Process private storage, shared between the members of a team
+pub type PSpace = Rc<RefCell<Vec<Address>>>;
 
 /// A mars process
 pub type Process = (Pid, PSpace, Threads);
 
 /// A corewars simulator
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct Mars {
   memory: Vec<Instruction>,
   p_space_size: usize,
+  /// Maximum number of threads per process
+  max_processes: usize,
+  max_cycles: usize,
+  max_length: usize,
+  min_distance: usize,
+  read_limit: usize,
+  write_limit: usize,
   cycle: usize,
+  /// Cycles left before the battle is a tie, counting the turns of every
+  /// process
+  cycles_left: usize,
   processes: VecDeque<Process>,
+  /// Last accesses to each cell, if tracking is enabled
+  cells: Option<Vec<CellHistory>>,
+  /// Changes made by the latest steps, if the Mars is reversible
+  undo: Option<VecDeque<UndoEntry>>,
+  /// Number of steps the undo log holds before it forgets the oldest
+  undo_limit: usize,
 }
 
 impl Mars {
@@ -59,7 +96,7 @@ impl Mars {
 
   /// Return all active procces ids
   pub fn pids(&self) -> impl Iterator<Item = Pid> + '_ {
-    self.processes.iter().cloned().map(|(pid, _, _)| pid)
+    self.processes.iter().map(|&(pid, _, _)| pid)
   }
 
   /// Returns each processes resources zipped with its pid
@@ -76,11 +113,53 @@ impl Mars {
   }
 
   /// Return process private storage(pspace) zipped with the owning process' id
-  pub fn process_pspaces(&self) -> impl Iterator<Item = (usize, &[Address])> {
+  pub fn process_pspaces(&self) -> impl Iterator<Item = (Pid, Ref<'_, [Address]>)> {
     self
       .processes
       .iter()
-      .map(|(pid, pspace, _)| (*pid, pspace.as_slice()))
+      .map(|(pid, pspace, _)| (*pid, Ref::map(pspace.borrow(), |x| x.as_slice())))
+  }
+
+  /// Return the size of each process' private storage
+  pub fn p_space_size(&self) -> usize {
+    self.p_space_size
+  }
+
+  /// Return the maximum number of threads each process can have
+  pub fn max_processes(&self) -> usize {
+    self.max_processes
+  }
+
+  /// Return the number of cycles each process gets before a battle is a tie
+  pub fn max_cycles(&self) -> usize {
+    self.max_cycles
+  }
+
+  /// Return the number of cycles left before the battle is a tie. Like pMARS
+  /// each process gets `max_cycles` turns, so this counts the turns of all of
+  /// the remaining processes
+  pub fn cycles_left(&self) -> usize {
+    self.cycles_left
+  }
+
+  /// Return the maximum number of instructions in a program
+  pub fn max_length(&self) -> usize {
+    self.max_length
+  }
+
+  /// Return the minimum distance between the first instructions of programs
+  pub fn min_distance(&self) -> usize {
+    self.min_distance
+  }
+
+  /// Return the range around the program counter that can be read from
+  pub fn read_limit(&self) -> usize {
+    self.read_limit
+  }
+
+  /// Return the range around the program counter that can be written to
+  pub fn write_limit(&self) -> usize {
+    self.write_limit
   }
 
   /// Returns the current number of processes
@@ -101,66 +180,177 @@ impl Mars {
     self.memory.as_slice()
   }
 
+  /// Return the last accesses to each cell of memory, or `None` if the Mars
+  /// wasn't built to track them
+  pub fn cell_history(&self) -> Option<&[CellHistory]> {
+    self.cells.as_deref()
+  }
+
+  /// Return a builder of a Mars with the same configuration
+  pub fn builder(&self) -> MarsBuilder {
+    MarsBuilder {
+      max_processes: self.max_processes,
+      p_space_size: self.p_space_size,
+      size: self.size(),
+      max_cycles: self.max_cycles,
+      max_length: self.max_length,
+      min_distance: self.min_distance,
+      read_limit: self.read_limit,
+      write_limit: self.write_limit,
+      track_cells: self.is_tracking(),
+      reversible: self.is_reversible(),
+      undo_limit: self.undo_limit,
+    }
+  }
+
+  /// Return true if the Mars records the last accesses to each cell
+  pub fn is_tracking(&self) -> bool {
+    self.cells.is_some()
+  }
+
+  /// Write instructions to memory starting at `address`
+  ///
+  /// Steps can't be undone across the write, so the recorded steps are
+  /// forgotten, and the history of the cells is left alone
   pub fn set_memory(&mut self, instructions: &[Instruction], address: Address) {
     let size = self.size();
-    for i in 0..instructions.len() {
-      self.memory[((address as usize + i) % size)] = instructions[i];
+    for (i, &instruction) in instructions.iter().enumerate() {
+      self.memory[(address as usize + i) % size] = self.normalize(instruction);
     }
+    self.clear_undo();
+  }
+
+  /// Load a program, which begins executing at its first instruction
+  ///
+  /// # Errors
+  /// Fails if the program is longer than the maximum length
+  pub fn load_program(
+    &mut self,
+    program: &[Instruction],
+    address: Address,
+  ) -> Result<Pid, MarsError> {
+    let pspace = self.new_pspace();
+    self.load_program_with_pspace(program, address, 0, pspace)
   }
 
-  pub fn load_program(&mut self, program: &[Instruction], address: Address) -> Pid {
-    let pspace = Rc::new(vec![]);
-    self.load_program_with_pspace(program, address, pspace)
+  /// Load a warrior, which begins executing at its start offset
+  ///
+  /// # Errors
+  /// Fails if the warrior is longer than the maximum length
+  pub fn load_warrior(&mut self, warrior: &Warrior, address: Address) -> Result<Pid, MarsError> {
+    let pspace = self.new_pspace();
+    self.load_program_with_pspace(&warrior.instructions, address, warrior.start, pspace)
+  }
+
+  /// Load a warrior that keeps its private storage in `pspace`, e.g. to carry
+  /// it over from a previous round
+  ///
+  /// # Errors
+  /// Fails if the warrior is longer than the maximum length
+  pub fn load_warrior_with_pspace(
+    &mut self,
+    warrior: &Warrior,
+    address: Address,
+    pspace: PSpace,
+  ) -> Result<Pid, MarsError> {
+    self.load_program_with_pspace(&warrior.instructions, address, warrior.start, pspace)
   }
 
   /// Load multiple programs in different locations with the same pspace
   ///
   /// # Returns
   /// A slice containing all of the created process ids, in order
+  ///
+  /// # Errors
+  /// Fails without loading any of the programs if one of them is longer than
+  /// the maximum length
   pub fn load_programs_with_shared_pspace(
     &mut self,
     programs: &[&[Instruction]],
     addresses: &[Address],
-  ) -> Vec<Pid> {
-    let pspace = Rc::new(vec![]);
+  ) -> Result<Vec<Pid>, MarsError> {
+    for program in programs {
+      self.check_length(program)?;
+    }
+
+    let pspace = self.new_pspace();
     let mut pids = vec![];
     for (program, &addr) in programs.iter().zip(addresses.iter()) {
-      let pid = self.load_program_with_pspace(program, addr, pspace.clone());
+      let pid = self.load_program_with_pspace(program, addr, 0, pspace.clone())?;
       pids.push(pid);
     }
 
-    pids
+    Ok(pids)
   }
 
   fn load_program_with_pspace(
     &mut self,
     program: &[Instruction],
     address: Address,
+    start: Address,
     pspace: PSpace,
-  ) -> Pid {
+  ) -> Result<Pid, MarsError> {
+    self.check_length(program)?;
+
     let pid = self.processes.len();
     let mut threads = VecDeque::new();
     self.set_memory(program, address);
-    threads.push_back(address);
+    if let Some(ref mut cells) = self.cells {
+      let size = cells.len();
+      let access = Access {
+        pid,
+        cycle: self.cycle,
+      };
+      for i in 0..program.len() {
+        cells[(address as usize + i) % size].write = Some(access);
+      }
+    }
+    threads.push_back((address + start) % self.size() as Address);
     self.processes.push_back((pid, pspace, threads));
-    pid
+    self.cycles_left += self.max_cycles;
+    Ok(pid)
+  }
+
+  fn check_length(&self, program: &[Instruction]) -> Result<(), MarsError> {
+    if program.len() > self.max_length {
+      Err(MarsError::ProgramTooLong {
+        length: program.len(),
+        max_length: self.max_length,
+      })
+    } else {
+      Ok(())
+    }
+  }
+
+  /// Create a new zeroed pspace. Cell 0 is reserved for the result of the
+  /// previous round and starts out as -1
+  pub fn new_pspace(&self) -> PSpace {
+    let mut pspace = vec![0; self.p_space_size];
+    pspace[0] = self.memory.len() as Address - 1;
+    Rc::new(RefCell::new(pspace))
   }
 
   /// Step forward one clock cycle
   ///
-  /// # Panics
-  /// panics if there are no processes in the Mars
+  /// # Errors
+  /// Fails if there are no processes in the Mars
+  pub fn step(&mut self) -> Result<StepOutcome, MarsError> {
+    self.step_with(&mut ())
+  }
+
+  /// Step forward one clock cycle, telling `observer` what happens
   ///
-  /// # Returns
-  /// `Some(pid)` if a process with id `pid` was killed. Otherwise `None`
-  pub fn step(&mut self) -> Option<Pid> {
-    assert!(
-      !self.processes.is_empty(),
-      "cannot execute with empty process queue"
-    );
+  /// # Errors
+  /// Fails if there are no processes in the Mars
+  pub fn step_with<O: Observer>(&mut self, observer: &mut O) -> Result<StepOutcome, MarsError> {
+    if self.processes.is_empty() {
+      return Err(MarsError::NoProcessesLeft);
+    }
+    let cycles_left = self.cycles_left;
     self.cycle += 1; // increment cycle
+    self.cycles_left = self.cycles_left.saturating_sub(1);
     let size = self.memory.len() as Address;
-    let (pid, mut pspace, mut threads) = self // dequeue the next process
+    let (pid, pspace, mut threads) = self // dequeue the next process
       .processes
       .pop_front()
       .expect("cannot step if no processes exist");
@@ -169,26 +359,55 @@ impl Mars {
       .expect("cannot execute a process with no threads");
     let instr = self.memory[(pc % size) as usize]; // fetch instruction from memory
 
-    let a_target_address = self.resolve_address(pc, instr.a.value, size, instr.a.mode);
-    let b_target_address = self.resolve_address(pc, instr.b.value, size, instr.b.mode);
+    // the A operand is only read from, the B operand is read from and written
+    // to, each access folded by its own limit
+    let read_limit = self.read_limit as Address;
+    let write_limit = self.write_limit as Address;
+    let a_target_address = self.resolve_address(pc, instr.a.value, size, instr.a.mode, read_limit);
+    let b_read_address = self.resolve_address(pc, instr.b.value, size, instr.b.mode, read_limit);
+    let b_write_address = self.resolve_address(pc, instr.b.value, size, instr.b.mode, write_limit);
+    observer.observe(&Event::InstructionExecuted {
+      pid,
+      cycle: self.cycle,
+      pc,
+      instruction: instr,
+      a_pointer: a_target_address % size,
+      b_pointer: b_write_address % size,
+    });
+
+    // cells the instruction can change, to report the ones it does
+    let pointer = |mode: AddressingMode, value: Address| match mode {
+      AIndirect(..) | BIndirect(..) => Some((pc + value) % size),
+      Immediate | Direct => None,
+    };
+    let changeable = [
+      pointer(instr.a.mode, instr.a.value),
+      pointer(instr.b.mode, instr.b.value),
+      Some(b_write_address % size),
+    ];
+    let before = changeable.map(|address| address.map(|address| self.memory[address as usize]));
 
     // Preincrement phase
     match instr.a.mode {
       AIndirect(IncrementMode::PreDecrement) => {
-        self.memory[((pc + instr.a.value) % size) as usize].a.value -= 1
+        let pointer = &mut self.memory[((pc + instr.a.value) % size) as usize];
+        pointer.a.value = (pointer.a.value + size - 1) % size;
       }
       BIndirect(IncrementMode::PreDecrement) => {
-        self.memory[((pc + instr.a.value) % size) as usize].b.value -= 1
+        let pointer = &mut self.memory[((pc + instr.a.value) % size) as usize];
+        pointer.b.value = (pointer.b.value + size - 1) % size;
       }
       _ => {}
     }
 
     match instr.b.mode {
       AIndirect(IncrementMode::PreDecrement) => {
-        self.memory[((pc + instr.b.value) % size) as usize].a.value -= 1
+        let pointer = &mut self.memory[((pc + instr.b.value) % size) as usize];
+        pointer.a.value = (pointer.a.value + size - 1) % size;
       }
       BIndirect(IncrementMode::PreDecrement) => {
-        self.memory[((pc + instr.b.value) % size) as usize].b.value -= 1
+        let pointer = &mut self.memory[((pc + instr.b.value) % size) as usize];
+        pointer.b.value = (pointer.b.value + size - 1) % size;
       }
       _ => {}
     }
@@ -196,9 +415,18 @@ impl Mars {
     // Execution phase, if a valid instruction was executed Some(offset) is
     // is returned. the offset is the amount that the program counter is
     // incremented
+    // address of the thread started by a `SPL`
+    let mut split = None;
+    // kind, index, value and previous value of a P-space access
+    let mut pspace_access = None;
+    let b_before = self.memory[(b_write_address % size) as usize];
+
     let maybe_offset = {
       let a_ptr = self.memory[(a_target_address % size) as usize];
-      let b_ptr = &mut self.memory[(b_target_address % size) as usize];
+      // the B operand is read from a copy of the cell before any write, which
+      // is where `DJN` tests its decremented value
+      let mut b_value = self.memory[(b_read_address % size) as usize];
+      let b_ptr = &mut self.memory[(b_write_address % size) as usize];
 
       // Instruction execution phase
       use OpMode::*;
@@ -240,128 +468,128 @@ impl Mars {
 
         // Addition instructions
         (Add, A) => {
-          b_ptr.a.value = (b_ptr.a.value + a_ptr.a.value) % size;
+          b_ptr.a.value = (b_value.a.value + a_ptr.a.value) % size;
           Some(1)
         }
         (Add, B) => {
-          b_ptr.b.value = (b_ptr.b.value + a_ptr.a.value) % size;
+          b_ptr.b.value = (b_value.b.value + a_ptr.a.value) % size;
           Some(1)
         }
         (Add, AB) => {
-          b_ptr.b.value = (b_ptr.a.value + a_ptr.b.value) % size;
+          b_ptr.b.value = (b_value.a.value + a_ptr.b.value) % size;
           Some(1)
         }
         (Add, BA) => {
-          b_ptr.a.value = (b_ptr.b.value + a_ptr.a.value) % size;
+          b_ptr.a.value = (b_value.b.value + a_ptr.a.value) % size;
           Some(1)
         }
         (Add, X) => {
-          b_ptr.b.value = (b_ptr.a.value + a_ptr.b.value) % size;
-          b_ptr.a.value = (b_ptr.b.value + a_ptr.a.value) % size;
+          b_ptr.b.value = (b_value.a.value + a_ptr.b.value) % size;
+          b_ptr.a.value = (b_value.b.value + a_ptr.a.value) % size;
           Some(1)
         }
         (Add, I) | (Add, F) => {
-          b_ptr.a.value = (b_ptr.a.value + a_ptr.a.value) % size;
-          b_ptr.b.value = (b_ptr.b.value + a_ptr.b.value) % size;
+          b_ptr.a.value = (b_value.a.value + a_ptr.a.value) % size;
+          b_ptr.b.value = (b_value.b.value + a_ptr.b.value) % size;
           Some(1)
         }
 
         // Subtraction instructions
         (Sub, A) => {
-          b_ptr.a.value = (b_ptr.a.value + size - a_ptr.a.value) % size;
+          b_ptr.a.value = (b_value.a.value + size - a_ptr.a.value) % size;
           Some(1)
         }
         (Sub, B) => {
-          b_ptr.b.value = (b_ptr.b.value + size - a_ptr.a.value) % size;
+          b_ptr.b.value = (b_value.b.value + size - a_ptr.a.value) % size;
           Some(1)
         }
         (Sub, AB) => {
-          b_ptr.b.value = (b_ptr.a.value + size - a_ptr.b.value) % size;
+          b_ptr.b.value = (b_value.a.value + size - a_ptr.b.value) % size;
           Some(1)
         }
         (Sub, BA) => {
-          b_ptr.a.value = (b_ptr.b.value + size - a_ptr.a.value) % size;
+          b_ptr.a.value = (b_value.b.value + size - a_ptr.a.value) % size;
           Some(1)
         }
         (Sub, X) => {
-          b_ptr.b.value = (b_ptr.a.value + size - a_ptr.b.value) % size;
-          b_ptr.a.value = (b_ptr.b.value + size - a_ptr.a.value) % size;
+          b_ptr.b.value = (b_value.a.value + size - a_ptr.b.value) % size;
+          b_ptr.a.value = (b_value.b.value + size - a_ptr.a.value) % size;
           Some(1)
         }
         (Sub, I) | (Sub, F) => {
-          b_ptr.a.value = (b_ptr.a.value + size - a_ptr.a.value) % size;
-          b_ptr.b.value = (b_ptr.b.value + size - a_ptr.b.value) % size;
+          b_ptr.a.value = (b_value.a.value + size - a_ptr.a.value) % size;
+          b_ptr.b.value = (b_value.b.value + size - a_ptr.b.value) % size;
           Some(1)
         }
 
         // Multiplication instructions
         (Mul, A) => {
-          b_ptr.a.value = (b_ptr.a.value * a_ptr.a.value) % size;
+          b_ptr.a.value = (b_value.a.value * a_ptr.a.value) % size;
           Some(1)
         }
         (Mul, B) => {
-          b_ptr.b.value = (b_ptr.b.value * a_ptr.a.value) % size;
+          b_ptr.b.value = (b_value.b.value * a_ptr.a.value) % size;
           Some(1)
         }
         (Mul, AB) => {
-          b_ptr.b.value = (b_ptr.a.value * a_ptr.b.value) % size;
+          b_ptr.b.value = (b_value.a.value * a_ptr.b.value) % size;
           Some(1)
         }
         (Mul, BA) => {
-          b_ptr.a.value = (b_ptr.b.value * a_ptr.a.value) % size;
+          b_ptr.a.value = (b_value.b.value * a_ptr.a.value) % size;
           Some(1)
         }
         (Mul, X) => {
-          b_ptr.b.value = (b_ptr.a.value * a_ptr.b.value) % size;
-          b_ptr.a.value = (b_ptr.b.value * a_ptr.a.value) % size;
+          b_ptr.b.value = (b_value.a.value * a_ptr.b.value) % size;
+          b_ptr.a.value = (b_value.b.value * a_ptr.a.value) % size;
           Some(1)
         }
         (Mul, I) | (Mul, F) => {
-          b_ptr.a.value = (b_ptr.a.value * a_ptr.a.value) % size;
-          b_ptr.b.value = (b_ptr.b.value * a_ptr.b.value) % size;
+          b_ptr.a.value = (b_value.a.value * a_ptr.a.value) % size;
+          b_ptr.b.value = (b_value.b.value * a_ptr.b.value) % size;
           Some(1)
         }
 
         // Division instructions
         (Div, A) => {
-          if a_ptr.a.value != 0 {
-            b_ptr.a.value = (b_ptr.a.value / a_ptr.a.value) % size;
+          if let Some(value) = b_value.a.value.checked_div(a_ptr.a.value) {
+            b_ptr.a.value = value % size;
             Some(1)
           } else {
             None
           }
         }
         (Div, B) => {
-          if a_ptr.b.value != 0 {
-            b_ptr.b.value = (b_ptr.b.value / a_ptr.b.value) % size;
+          if let Some(value) = b_value.b.value.checked_div(a_ptr.b.value) {
+            b_ptr.b.value = value % size;
             Some(1)
           } else {
             None
           }
         }
         (Div, AB) => {
-          if a_ptr.a.value != 0 {
-            b_ptr.b.value = (b_ptr.b.value / a_ptr.a.value) % size;
+          if let Some(value) = b_value.b.value.checked_div(a_ptr.a.value) {
+            b_ptr.b.value = value % size;
             Some(1)
           } else {
             None
           }
         }
         (Div, BA) => {
-          if a_ptr.b.value != 0 {
-            b_ptr.a.value = (b_ptr.a.value / a_ptr.b.value) % size;
+          if let Some(value) = b_value.a.value.checked_div(a_ptr.b.value) {
+            b_ptr.a.value = value % size;
             Some(1)
           } else {
             None
           }
         }
         (Div, X) => {
-          if a_ptr.a.value != 0 {
-            b_ptr.b.value = (b_ptr.b.value / a_ptr.a.value) % size;
+          if let Some(value) = b_value.b.value.checked_div(a_ptr.a.value) {
+            b_ptr.b.value = value % size;
           }
 
-          if a_ptr.b.value != 0 {
-            b_ptr.a.value = (b_ptr.a.value / a_ptr.b.value) % size;
+          if let Some(value) = b_value.a.value.checked_div(a_ptr.b.value) {
+            b_ptr.a.value = value % size;
           }
 
           if a_ptr.a.value == 0 || a_ptr.b.value == 0 {
@@ -371,12 +599,12 @@ impl Mars {
           }
         }
         (Div, F) | (Div, I) => {
-          if a_ptr.a.value != 0 {
-            b_ptr.a.value = (b_ptr.a.value / a_ptr.a.value) % size;
+          if let Some(value) = b_value.a.value.checked_div(a_ptr.a.value) {
+            b_ptr.a.value = value % size;
           }
 
-          if a_ptr.b.value != 0 {
-            b_ptr.b.value = (b_ptr.b.value / a_ptr.b.value) % size;
+          if let Some(value) = b_value.b.value.checked_div(a_ptr.b.value) {
+            b_ptr.b.value = value % size;
           }
 
           if a_ptr.a.value == 0 || a_ptr.b.value == 0 {
@@ -389,7 +617,7 @@ impl Mars {
         // Modulo instructions
         (Mod, A) => {
           if a_ptr.a.value != 0 {
-            b_ptr.a.value = (b_ptr.a.value % a_ptr.a.value) % size;
+            b_ptr.a.value = (b_value.a.value % a_ptr.a.value) % size;
             Some(1)
           } else {
             None
@@ -397,7 +625,7 @@ impl Mars {
         }
         (Mod, B) => {
           if a_ptr.b.value != 0 {
-            b_ptr.b.value = (b_ptr.b.value % a_ptr.b.value) % size;
+            b_ptr.b.value = (b_value.b.value % a_ptr.b.value) % size;
             Some(1)
           } else {
             None
@@ -405,7 +633,7 @@ impl Mars {
         }
         (Mod, AB) => {
           if a_ptr.a.value != 0 {
-            b_ptr.b.value = (b_ptr.b.value % a_ptr.a.value) % size;
+            b_ptr.b.value = (b_value.b.value % a_ptr.a.value) % size;
             Some(1)
           } else {
             None
@@ -413,7 +641,7 @@ impl Mars {
         }
         (Mod, BA) => {
           if a_ptr.b.value != 0 {
-            b_ptr.a.value = (b_ptr.a.value % a_ptr.b.value) % size;
+            b_ptr.a.value = (b_value.a.value % a_ptr.b.value) % size;
             Some(1)
           } else {
             None
@@ -421,11 +649,11 @@ impl Mars {
         }
         (Mod, X) => {
           if a_ptr.a.value != 0 {
-            b_ptr.b.value = (b_ptr.b.value % a_ptr.a.value) % size;
+            b_ptr.b.value = (b_value.b.value % a_ptr.a.value) % size;
           }
 
           if a_ptr.b.value != 0 {
-            b_ptr.a.value = (b_ptr.a.value % a_ptr.b.value) % size;
+            b_ptr.a.value = (b_value.a.value % a_ptr.b.value) % size;
           }
 
           if a_ptr.a.value == 0 || a_ptr.b.value == 0 {
@@ -436,11 +664,11 @@ impl Mars {
         }
         (Mod, F) | (Mod, I) => {
           if a_ptr.a.value != 0 {
-            b_ptr.a.value = (b_ptr.a.value % a_ptr.a.value) % size;
+            b_ptr.a.value = (b_value.a.value % a_ptr.a.value) % size;
           }
 
           if a_ptr.b.value != 0 {
-            b_ptr.b.value = (b_ptr.b.value % a_ptr.b.value) % size;
+            b_ptr.b.value = (b_value.b.value % a_ptr.b.value) % size;
           }
 
           if a_ptr.a.value == 0 || a_ptr.b.value == 0 {
@@ -454,21 +682,21 @@ impl Mars {
         (Jmp, _) => Some(instr.a.value),
 
         (Jmz, A) | (Jmz, BA) => {
-          if b_ptr.a.value == 0 {
+          if b_value.a.value == 0 {
             Some(instr.a.value)
           } else {
             Some(1)
           }
         }
         (Jmz, B) | (Jmz, AB) => {
-          if b_ptr.b.value == 0 {
+          if b_value.b.value == 0 {
             Some(instr.a.value)
           } else {
             Some(1)
           }
         }
         (Jmz, F) | (Jmz, X) | (Jmz, I) => {
-          if b_ptr.a.value == 0 && b_ptr.b.value == 0 {
+          if b_value.a.value == 0 && b_value.b.value == 0 {
             Some(instr.a.value)
           } else {
             Some(1)
@@ -477,21 +705,21 @@ impl Mars {
 
         // Always in B-mode
         (Jmn, A) | (Jmn, BA) => {
-          if b_ptr.a.value != 0 {
+          if b_value.a.value != 0 {
             Some(instr.a.value)
           } else {
             Some(1)
           }
         }
         (Jmn, B) | (Jmn, AB) => {
-          if b_ptr.b.value != 0 {
+          if b_value.b.value != 0 {
             Some(instr.a.value)
           } else {
             Some(1)
           }
         }
         (Jmn, F) | (Jmn, X) | (Jmn, I) => {
-          if b_ptr.a.value != 0 && b_ptr.b.value != 0 {
+          if b_value.a.value != 0 && b_value.b.value != 0 {
             Some(instr.a.value)
           } else {
             Some(1)
@@ -500,6 +728,7 @@ impl Mars {
 
         (Djn, A) | (Djn, BA) => {
           b_ptr.a.value = (b_ptr.a.value + size - 1) % size;
+          b_value.a.value = (b_value.a.value + size - 1) % size;
           // immutable increment a field of instr by one,
           let instr = Instruction {
             a: Field {
@@ -509,7 +738,7 @@ impl Mars {
             ..instr
           };
 
-          if b_ptr.a.value != 0 {
+          if b_value.a.value != 0 {
             Some(instr.a.value)
           } else {
             Some(1)
@@ -517,6 +746,7 @@ impl Mars {
         }
         (Djn, B) | (Djn, AB) => {
           b_ptr.b.value = (b_ptr.b.value + size - 1) % size;
+          b_value.b.value = (b_value.b.value + size - 1) % size;
           // immutable increment a field of instr by one,
           let instr = Instruction {
             b: Field {
@@ -526,7 +756,7 @@ impl Mars {
             ..instr
           };
 
-          if b_ptr.a.value != 0 {
+          if b_value.a.value != 0 {
             Some(instr.a.value)
           } else {
             Some(1)
@@ -534,7 +764,9 @@ impl Mars {
         }
         (Djn, F) | (Djn, X) | (Djn, I) => {
           b_ptr.b.value = (b_ptr.b.value + size - 1) % size;
+          b_value.b.value = (b_value.b.value + size - 1) % size;
           b_ptr.a.value = (b_ptr.a.value + size - 1) % size;
+          b_value.a.value = (b_value.a.value + size - 1) % size;
           // immutable increment a field of instr by one,
           let instr = Instruction {
             a: Field {
@@ -548,7 +780,7 @@ impl Mars {
             ..instr
           };
 
-          if b_ptr.a.value != 0 || b_ptr.b.value != 0 {
+          if b_value.a.value != 0 || b_value.b.value != 0 {
             Some(instr.a.value)
           } else {
             Some(1)
@@ -557,56 +789,56 @@ impl Mars {
 
         // Split instructions
         (Spl, _) => {
-          // Start new thread by queuing new program counter
-          threads.push_back(pc + instr.a.value);
+          // the new thread is queued after the next instruction
+          split = Some((pc + instr.a.value) % size);
           Some(1)
         }
 
         //
         (Seq, A) | (Cmp, A) => {
-          if a_ptr.a.value == b_ptr.a.value {
+          if a_ptr.a.value == b_value.a.value {
             Some(2)
           } else {
             Some(1)
           }
         }
         (Seq, B) | (Cmp, B) => {
-          if a_ptr.b.value == b_ptr.b.value {
+          if a_ptr.b.value == b_value.b.value {
             Some(2)
           } else {
             Some(1)
           }
         }
         (Seq, AB) | (Cmp, AB) => {
-          if a_ptr.a.value == b_ptr.b.value {
+          if a_ptr.a.value == b_value.b.value {
             Some(2)
           } else {
             Some(1)
           }
         }
         (Seq, BA) | (Cmp, BA) => {
-          if a_ptr.b.value == b_ptr.a.value {
+          if a_ptr.b.value == b_value.a.value {
             Some(2)
           } else {
             Some(1)
           }
         }
         (Seq, F) | (Cmp, F) => {
-          if a_ptr.a.value == b_ptr.a.value && a_ptr.b.value == b_ptr.b.value {
+          if a_ptr.a.value == b_value.a.value && a_ptr.b.value == b_value.b.value {
             Some(2)
           } else {
             Some(1)
           }
         }
         (Seq, X) | (Cmp, X) => {
-          if a_ptr.a.value == b_ptr.b.value && a_ptr.b.value == b_ptr.a.value {
+          if a_ptr.a.value == b_value.b.value && a_ptr.b.value == b_value.a.value {
             Some(2)
           } else {
             Some(1)
           }
         }
         (Seq, I) | (Cmp, I) => {
-          if a_ptr == *b_ptr {
+          if a_ptr == b_value {
             Some(2)
           } else {
             Some(1)
@@ -614,42 +846,42 @@ impl Mars {
         }
 
         (Slt, A) => {
-          if a_ptr.a.value < b_ptr.a.value {
+          if a_ptr.a.value < b_value.a.value {
             Some(2)
           } else {
             Some(1)
           }
         }
         (Slt, B) => {
-          if a_ptr.b.value < b_ptr.b.value {
+          if a_ptr.b.value < b_value.b.value {
             Some(2)
           } else {
             Some(1)
           }
         }
         (Slt, AB) => {
-          if a_ptr.a.value < b_ptr.b.value {
+          if a_ptr.a.value < b_value.b.value {
             Some(2)
           } else {
             Some(1)
           }
         }
         (Slt, BA) => {
-          if a_ptr.b.value < b_ptr.a.value {
+          if a_ptr.b.value < b_value.a.value {
             Some(2)
           } else {
             Some(1)
           }
         }
         (Slt, F) | (Slt, I) => {
-          if a_ptr.a.value < b_ptr.a.value && a_ptr.b.value < b_ptr.b.value {
+          if a_ptr.a.value < b_value.a.value && a_ptr.b.value < b_value.b.value {
             Some(2)
           } else {
             Some(1)
           }
         }
         (Slt, X) => {
-          if a_ptr.a.value < b_ptr.b.value && a_ptr.b.value < b_ptr.a.value {
+          if a_ptr.a.value < b_value.b.value && a_ptr.b.value < b_value.a.value {
             Some(2)
           } else {
             Some(1)
@@ -657,70 +889,89 @@ impl Mars {
         }
 
         (Sne, A) => {
-          if a_ptr.a.value == b_ptr.a.value {
+          if a_ptr.a.value == b_value.a.value {
             Some(2)
           } else {
             Some(1)
           }
         }
         (Sne, B) => {
-          if a_ptr.b.value == b_ptr.b.value {
+          if a_ptr.b.value == b_value.b.value {
             Some(2)
           } else {
             Some(1)
           }
         }
         (Sne, AB) => {
-          if a_ptr.a.value == b_ptr.b.value {
+          if a_ptr.a.value == b_value.b.value {
             Some(2)
           } else {
             Some(1)
           }
         }
         (Sne, BA) => {
-          if a_ptr.b.value == b_ptr.a.value {
+          if a_ptr.b.value == b_value.a.value {
             Some(2)
           } else {
             Some(1)
           }
         }
         (Sne, F) => {
-          if a_ptr.a.value == b_ptr.a.value && a_ptr.b.value == b_ptr.b.value {
+          if a_ptr.a.value == b_value.a.value && a_ptr.b.value == b_value.b.value {
             Some(2)
           } else {
             Some(1)
           }
         }
         (Sne, X) => {
-          if a_ptr.a.value == b_ptr.b.value && a_ptr.b.value == b_ptr.a.value {
+          if a_ptr.a.value == b_value.b.value && a_ptr.b.value == b_value.a.value {
             Some(2)
           } else {
             Some(1)
           }
         }
         (Sne, I) => {
-          if a_ptr == *b_ptr {
+          if a_ptr == b_value {
             Some(2)
           } else {
             Some(1)
           }
         }
 
-        (Ldp, A) => unimplemented!(),
-        (Ldp, B) => unimplemented!(),
-        (Ldp, AB) => unimplemented!(),
-        (Ldp, BA) => unimplemented!(),
-        (Ldp, F) => unimplemented!(),
-        (Ldp, X) => unimplemented!(),
-        (Ldp, I) => unimplemented!(),
+        // P-space instructions, .F, .X and .I behave like .B
+        (Ldp, A) => {
+          b_ptr.a.value = p_space_load(&pspace, a_ptr.a.value, &mut pspace_access);
+          Some(1)
+        }
+        (Ldp, B) | (Ldp, F) | (Ldp, X) | (Ldp, I) => {
+          b_ptr.b.value = p_space_load(&pspace, a_ptr.b.value, &mut pspace_access);
+          Some(1)
+        }
+        (Ldp, AB) => {
+          b_ptr.b.value = p_space_load(&pspace, a_ptr.a.value, &mut pspace_access);
+          Some(1)
+        }
+        (Ldp, BA) => {
+          b_ptr.a.value = p_space_load(&pspace, a_ptr.b.value, &mut pspace_access);
+          Some(1)
+        }
 
-        (Stp, A) => unimplemented!(),
-        (Stp, B) => unimplemented!(),
-        (Stp, AB) => unimplemented!(),
-        (Stp, BA) => unimplemented!(),
-        (Stp, F) => unimplemented!(),
-        (Stp, X) => unimplemented!(),
-        (Stp, I) => unimplemented!(),
+        (Stp, A) => {
+          p_space_store(&pspace, b_value.a.value, a_ptr.a.value, &mut pspace_access);
+          Some(1)
+        }
+        (Stp, B) | (Stp, F) | (Stp, X) | (Stp, I) => {
+          p_space_store(&pspace, b_value.b.value, a_ptr.b.value, &mut pspace_access);
+          Some(1)
+        }
+        (Stp, AB) => {
+          p_space_store(&pspace, b_value.b.value, a_ptr.a.value, &mut pspace_access);
+          Some(1)
+        }
+        (Stp, BA) => {
+          p_space_store(&pspace, b_value.a.value, a_ptr.b.value, &mut pspace_access);
+          Some(1)
+        }
 
         (Nop, _) => Some(1),
       }
@@ -729,36 +980,139 @@ impl Mars {
     // post increment
     match instr.a.mode {
       AIndirect(IncrementMode::PostIncrement) => {
-        self.memory[(pc + instr.a.value) as usize].a.value += 1
+        let pointer = &mut self.memory[((pc + instr.a.value) % size) as usize];
+        pointer.a.value = (pointer.a.value + 1) % size;
       }
       BIndirect(IncrementMode::PostIncrement) => {
-        self.memory[(pc + instr.a.value) as usize].b.value += 1
+        let pointer = &mut self.memory[((pc + instr.a.value) % size) as usize];
+        pointer.b.value = (pointer.b.value + 1) % size;
       }
       _ => {}
     }
 
     match instr.b.mode {
       AIndirect(IncrementMode::PostIncrement) => {
-        self.memory[(pc + instr.b.value) as usize].a.value += 1
+        let pointer = &mut self.memory[((pc + instr.b.value) % size) as usize];
+        pointer.a.value = (pointer.a.value + 1) % size;
       }
       BIndirect(IncrementMode::PostIncrement) => {
-        self.memory[(pc + instr.b.value) as usize].b.value += 1
+        let pointer = &mut self.memory[((pc + instr.b.value) % size) as usize];
+        pointer.b.value = (pointer.b.value + 1) % size;
       }
       _ => {}
     }
 
+    // history of the accessed cells, to undo the step
+    let mut old_cells = vec![];
+    if let (Some(cells), Some(_)) = (&self.cells, &self.undo) {
+      let pointers = changeable[..2].iter().filter_map(|&address| address);
+      for address in [pc, a_target_address, b_read_address, b_write_address]
+        .iter()
+        .map(|address| address % size)
+        .chain(pointers)
+      {
+        old_cells.push((address, cells[address as usize]));
+      }
+    }
+
+    if let Some(ref mut cells) = self.cells {
+      let b_written =
+        maybe_offset.is_some() || self.memory[(b_write_address % size) as usize] != b_before;
+      let access = Access {
+        pid,
+        cycle: self.cycle,
+      };
+      tracking::record_step(
+        cells,
+        access,
+        pc,
+        &instr,
+        a_target_address,
+        b_read_address,
+        Some(b_write_address).filter(|_| b_written),
+      );
+    }
+
+    let mut old_memory = vec![];
+    for (i, (address, old)) in changeable.iter().zip(&before).enumerate() {
+      if let (&Some(address), &Some(old)) = (address, old) {
+        let new = self.memory[address as usize];
+        // a cell is only reported once, even if several operands refer to it
+        if new != old && !changeable[..i].contains(&Some(address)) {
+          old_memory.push((address, old));
+          observer.observe(&Event::MemoryWritten {
+            pid,
+            address,
+            old,
+            new,
+          });
+        }
+      }
+    }
+
+    if let Some((kind, index, value, _)) = pspace_access {
+      observer.observe(&Event::PSpaceAccess {
+        pid,
+        kind,
+        index,
+        value,
+      });
+    }
+
     // requeue the program counter if the thread wasn't killed
-    if let Some(offset) = maybe_offset {
-      threads.push_back((pc + offset) % size);
+    let next_pc = maybe_offset.map(|offset| (pc + offset) % size);
+    if let Some(next_pc) = next_pc {
+      threads.push_back(next_pc);
+    }
+
+    // new threads aren't started once the process has reached its limit
+    let split = split.filter(|_| threads.len() < self.max_processes);
+    if let Some(address) = split {
+      threads.push_back(address);
+      observer.observe(&Event::ThreadSplit { pid, address });
+    }
+
+    if maybe_offset.is_none() {
+      observer.observe(&Event::ThreadKilled { pid, pc });
     }
 
     // requeue the process if there are still threads
-    if !threads.is_empty() {
+    let process_killed = threads.is_empty();
+    let killed_pspace = if !process_killed {
       self.processes.push_back((pid, pspace, threads));
       None
     } else {
-      Some(pid)
+      // the remaining processes keep the turns they had left
+      self.cycles_left -= self.cycles_left / (self.processes.len() + 1);
+      observer.observe(&Event::ProcessKilled { pid });
+      Some(pspace)
+    };
+
+    let outcome = StepOutcome {
+      pid,
+      pc,
+      instruction: instr,
+      next_pc,
+      split,
+      process_killed,
+    };
+    if let Some(ref mut undo) = self.undo {
+      undo.push_back(UndoEntry {
+        outcome,
+        cycles_left,
+        pspace: killed_pspace,
+        memory: old_memory,
+        pspace_store: pspace_access.and_then(|(kind, index, _, previous)| match kind {
+          PSpaceAccessKind::Store => Some((index, previous)),
+          PSpaceAccessKind::Load => None,
+        }),
+        cells: old_cells,
+      });
+      if undo.len() > self.undo_limit {
+        undo.pop_front();
+      }
     }
+    Ok(outcome)
   }
 
   /// Reset the mars
@@ -771,8 +1125,17 @@ impl Mars {
       .iter_mut()
       .for_each(|x| *x = Instruction::default());
 
+    // clear the history of every cell
+    if let Some(ref mut cells) = self.cells {
+      cells
+        .iter_mut()
+        .for_each(|cell| *cell = CellHistory::default());
+    }
+
     // clear process queue
     self.processes.clear();
+    self.clear_undo();
+    self.cycles_left = 0;
     self
   }
 
@@ -784,18 +1147,29 @@ impl Mars {
   /// * `offset`: offset to add to program counter
   /// * `size`: size of core
   /// * `addr_mode`: method to resolve address
+  /// * `limit`: range around the program counter the address is folded into
   fn resolve_address(
     &self,
     pc: Address,
     offset: Address,
     size: u32,
     addr_mode: AddressingMode,
+    limit: u32,
   ) -> Address {
-    pc + match addr_mode {
+    let offset = match addr_mode {
       Direct => offset,
       AIndirect(..) => offset + self.memory[((pc + offset) % size) as usize].a.value,
       BIndirect(..) => offset + self.memory[((pc + offset) % size) as usize].b.value,
       Immediate => 0,
+    };
+
+    // offsets past the middle of the limit reach backwards from the program
+    // counter. Without a limit this leaves the address unchanged
+    let offset = offset % limit;
+    if offset > limit / 2 {
+      pc + offset + size - limit
+    } else {
+      pc + offset
     }
   }
 
@@ -814,13 +1188,97 @@ impl Mars {
   }
 }
 
-// TODO: define default later
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// A P-space access, see `Event::PSpaceAccess`, along with the value the
+/// cell held before
+type PSpaceAccess = Option<(PSpaceAccessKind, Address, Address, Address)>;
+
+/// Load a value from a pspace, folding the index into the pspace
+fn p_space_load(pspace: &PSpace, index: Address, access: &mut PSpaceAccess) -> Address {
+  let pspace = pspace.borrow();
+  let index = index as usize % pspace.len();
+  let value = pspace[index];
+  *access = Some((PSpaceAccessKind::Load, index as Address, value, value));
+  value
+}
+
+/// Store a value in a pspace, folding the index into the pspace
+fn p_space_store(pspace: &PSpace, index: Address, value: Address, access: &mut PSpaceAccess) {
+  let mut pspace = pspace.borrow_mut();
+  let index = index as usize % pspace.len();
+  *access = Some((
+    PSpaceAccessKind::Store,
+    index as Address,
+    value,
+    pspace[index],
+  ));
+  pspace[index] = value;
+}
+
+/// An error describing an invalid `MarsBuilder` configuration, or a `Mars`
+/// that can't step or load a program
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Fail)]
+pub enum MarsError {
+  #[fail(display = "core size must be greater than 0")]
+  EmptyCore,
+  #[fail(
+    display = "P-space size {} does not divide the core size {}",
+    p_space_size, size
+  )]
+  InvalidPSpaceSize { p_space_size: usize, size: usize },
+  #[fail(
+    display = "read limit {} does not divide the core size {}",
+    limit, size
+  )]
+  InvalidReadLimit { limit: usize, size: usize },
+  #[fail(
+    display = "write limit {} does not divide the core size {}",
+    limit, size
+  )]
+  InvalidWriteLimit { limit: usize, size: usize },
+  #[fail(display = "maximum number of processes must be greater than 0")]
+  NoProcesses,
+  #[fail(display = "maximum number of cycles must be greater than 0")]
+  NoCycles,
+  #[fail(
+    display = "maximum length {} is greater than the core size {}",
+    max_length, size
+  )]
+  InvalidMaxLength { max_length: usize, size: usize },
+  #[fail(
+    display = "minimum distance {} is less than the maximum length {}",
+    min_distance, max_length
+  )]
+  InvalidMinDistance {
+    min_distance: usize,
+    max_length: usize,
+  },
+  #[fail(display = "there are no processes left to execute")]
+  NoProcessesLeft,
+  #[fail(
+    display = "program of length {} is longer than the maximum length {}",
+    length, max_length
+  )]
+  ProgramTooLong { length: usize, max_length: usize },
+}
+
+/// Configuration of a `Mars`, which defaults to the standard '94 hill
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct MarsBuilder {
   max_processes: usize,
   /// Size of private storage
   p_space_size: usize,
   size: usize,
+  max_cycles: usize,
+  max_length: usize,
+  min_distance: usize,
+  read_limit: usize,
+  write_limit: usize,
+  /// Whether to record the last accesses to each cell
+  track_cells: bool,
+  /// Whether to record the changes of every step so they can be undone
+  reversible: bool,
+  /// Number of steps that can be undone
+  undo_limit: usize,
 }
 
 impl MarsBuilder {
@@ -828,10 +1286,20 @@ impl MarsBuilder {
   pub fn new(size: usize) -> Self {
     MarsBuilder {
       size,
-      ..Self::default()
+      p_space_size: MARS_DEFAULT_P_SPACE_SIZE,
+      max_processes: MARS_DEFAULT_MAX_PROCESSES,
+      max_cycles: MARS_DEFAULT_MAX_CYCLES,
+      max_length: MARS_DEFAULT_MAX_LENGTH,
+      min_distance: MARS_DEFAULT_MIN_DISTANCE,
+      read_limit: size,
+      write_limit: size,
+      track_cells: false,
+      reversible: false,
+      undo_limit: MARS_DEFAULT_UNDO_LIMIT,
     }
   }
 
+  /// Set the maximum number of threads each process can have
   pub fn max_processes(self, value: usize) -> Self {
     Self {
       max_processes: value,
@@ -846,39 +1314,268 @@ impl MarsBuilder {
     }
   }
 
+  /// Set the number of cycles each process gets before the battle is a tie
+  pub fn max_cycles(self, value: usize) -> Self {
+    Self {
+      max_cycles: value,
+      ..self
+    }
+  }
+
+  /// Set the maximum number of instructions in a program
+  pub fn max_length(self, value: usize) -> Self {
+    Self {
+      max_length: value,
+      ..self
+    }
+  }
+
+  /// Set the minimum distance between the first instructions of programs
+  pub fn min_distance(self, value: usize) -> Self {
+    Self {
+      min_distance: value,
+      ..self
+    }
+  }
+
+  /// Set the range around the program counter that instructions can read from
+  pub fn read_limit(self, value: usize) -> Self {
+    Self {
+      read_limit: value,
+      ..self
+    }
+  }
+
+  /// Set the range around the program counter that instructions can write to
+  pub fn write_limit(self, value: usize) -> Self {
+    Self {
+      write_limit: value,
+      ..self
+    }
+  }
+
+  /// Return the name and value of every setting that affects battles,
+  /// starting with the size, so they can be saved and restored with
+  /// `setting`
+  pub fn settings(&self) -> [(&'static str, usize); 8] {
+    [
+      ("size", self.size),
+      ("p_space_size", self.p_space_size),
+      ("max_processes", self.max_processes),
+      ("max_cycles", self.max_cycles),
+      ("max_length", self.max_length),
+      ("min_distance", self.min_distance),
+      ("read_limit", self.read_limit),
+      ("write_limit", self.write_limit),
+    ]
+  }
+
+  /// Change the setting with a name returned by `settings`, or return `None`
+  /// if there is no such setting. Changing the size leaves the read and
+  /// write limits alone
+  pub fn setting(self, name: &str, value: usize) -> Option<Self> {
+    Some(match name {
+      "size" => Self {
+        size: value,
+        ..self
+      },
+      "p_space_size" => self.p_space_size(value),
+      "max_processes" => self.max_processes(value),
+      "max_cycles" => self.max_cycles(value),
+      "max_length" => self.max_length(value),
+      "min_distance" => self.min_distance(value),
+      "read_limit" => self.read_limit(value),
+      "write_limit" => self.write_limit(value),
+      _ => return None,
+    })
+  }
+
+  /// Set whether the Mars records which process last wrote, read and
+  /// executed each cell. This slows down every step, so it is off by default
+  pub fn track_cells(self, value: bool) -> Self {
+    Self {
+      track_cells: value,
+      ..self
+    }
+  }
+
+  /// Set whether the Mars records the changes made by every step, so that
+  /// `Mars::step_back` can undo them. The log grows with every step up to
+  /// the undo limit, so it is off by default
+  pub fn reversible(self, value: bool) -> Self {
+    Self {
+      reversible: value,
+      ..self
+    }
+  }
+
+  /// Set the number of steps a reversible Mars remembers. Once the limit is
+  /// reached, the oldest step is forgotten for every new one
+  pub fn undo_limit(self, value: usize) -> Self {
+    Self {
+      undo_limit: value,
+      ..self
+    }
+  }
+
   /// Build a `Mars`
-  pub fn build(&self) -> Mars {
-    let memory = vec![Instruction::default(); self.size];
+  ///
+  /// # Errors
+  /// The core size must be non-zero and divisible by the P-space size and by
+  /// the read and write limits, and programs must fit in the core and be no
+  /// longer than the minimum distance between them
+  pub fn build(&self) -> Result<Mars, MarsError> {
+    let size = self.size;
+    let divides_size = |value: usize| value != 0 && size.is_multiple_of(value);
+
+    if size == 0 {
+      return Err(MarsError::EmptyCore);
+    }
+    if !divides_size(self.p_space_size) {
+      return Err(MarsError::InvalidPSpaceSize {
+        p_space_size: self.p_space_size,
+        size,
+      });
+    }
+    if !divides_size(self.read_limit) {
+      return Err(MarsError::InvalidReadLimit {
+        limit: self.read_limit,
+        size,
+      });
+    }
+    if !divides_size(self.write_limit) {
+      return Err(MarsError::InvalidWriteLimit {
+        limit: self.write_limit,
+        size,
+      });
+    }
+    if self.max_processes == 0 {
+      return Err(MarsError::NoProcesses);
+    }
+    if self.max_cycles == 0 {
+      return Err(MarsError::NoCycles);
+    }
+    if self.max_length > size {
+      return Err(MarsError::InvalidMaxLength {
+        max_length: self.max_length,
+        size,
+      });
+    }
+    if self.min_distance < self.max_length {
+      return Err(MarsError::InvalidMinDistance {
+        min_distance: self.min_distance,
+        max_length: self.max_length,
+      });
+    }
 
-    Mars {
-      memory,
+    Ok(Mars {
+      memory: vec![Instruction::default(); size],
       p_space_size: self.p_space_size,
+      max_processes: self.max_processes,
+      max_cycles: self.max_cycles,
+      max_length: self.max_length,
+      min_distance: self.min_distance,
+      read_limit: self.read_limit,
+      write_limit: self.write_limit,
+      cycle: 0,
+      cycles_left: 0,
       processes: VecDeque::new(),
-      ..Mars::default()
-    }
+      cells: if self.track_cells {
+        Some(vec![CellHistory::default(); size])
+      } else {
+        None
+      },
+      undo: if self.reversible {
+        Some(VecDeque::new())
+      } else {
+        None
+      },
+      undo_limit: self.undo_limit,
+    })
   }
 }
 
-impl Default for Mars {
+impl Default for MarsBuilder {
   fn default() -> Self {
+    MarsBuilder::new(MARS_DEFAULT_SIZE)
+  }
+}
+
+impl<'a> From<&'a MarsBuilder> for Environment {
+  fn from(builder: &'a MarsBuilder) -> Self {
+    Environment {
+      core_size: builder.size,
+      p_space_size: builder.p_space_size,
+      max_processes: builder.max_processes,
+      max_cycles: builder.max_cycles,
+      max_length: builder.max_length,
+      min_distance: builder.min_distance,
+      ..Environment::default()
+    }
+  }
+}
+
+/// A clone gets its own copy of every P-space, so stepping it leaves the
+/// P-spaces of the original alone. Processes that share a P-space still share
+/// one in the clone
+impl Clone for Mars {
+  fn clone(&self) -> Self {
+    // handles of the original's P-spaces along with their copies
+    let mut copies: Vec<(PSpace, PSpace)> = vec![];
+    let mut copy = |pspace: &PSpace| {
+      if let Some((_, copy)) = copies
+        .iter()
+        .find(|(original, _)| Rc::ptr_eq(original, pspace))
+      {
+        return copy.clone();
+      }
+      let copy = Rc::new(RefCell::new(pspace.borrow().clone()));
+      copies.push((pspace.clone(), copy.clone()));
+      copy
+    };
+
+    let processes = self
+      .processes
+      .iter()
+      .map(|(pid, pspace, threads)| (*pid, copy(pspace), threads.clone()))
+      .collect();
+    let undo = self.undo.as_ref().map(|undo| {
+      undo
+        .iter()
+        .map(|entry| UndoEntry {
+          pspace: entry.pspace.as_ref().map(&mut copy),
+          ..entry.clone()
+        })
+        .collect()
+    });
+
     Mars {
-      memory: vec![Instruction::default(); MARS_DEFAULT_SIZE], // Make this a const
-      p_space_size: MARS_DEFAULT_P_SPACE_SIZE,
-      cycle: 0,
-      /// TODO: make this a const
-      processes: VecDeque::new(),
+      memory: self.memory.clone(),
+      processes,
+      cells: self.cells.clone(),
+      undo,
+      ..*self
     }
   }
 }
 
+impl Default for Mars {
+  fn default() -> Self {
+    MarsBuilder::default()
+      .build()
+      .expect("the default configuration is valid")
+  }
+}
+
 #[cfg(test)]
 mod test {
   use super::*;
+  use redcode::{OpCode, OpField, OpMode, OpMode::*};
 
   fn you_know_what_it_is(program: &[Instruction], addr: Address) -> Mars {
     let mut mars = Mars::default();
-    mars.load_program(program, addr);
-    mars.step();
+    mars.load_program(program, addr).unwrap();
+    mars.step().unwrap();
     mars
   }
 
@@ -907,8 +1604,8 @@ mod test {
       Instruction::new(Mov, A, Direct, 1, Direct, target_addr as Address),
       Instruction::new(Nop, F, Immediate, 100, Immediate, 100),
     ];
-    mars.load_program(program, 0);
-    mars.step();
+    mars.load_program(program, 0).unwrap();
+    mars.step().unwrap();
 
     let expected = Instruction {
       a: program[1].a,
@@ -926,8 +1623,8 @@ mod test {
       Instruction::new(Mov, B, Direct, 1, Direct, target_addr as Address),
       Instruction::new(Nop, F, Immediate, 100, Immediate, 100),
     ];
-    mars.load_program(program, 0);
-    mars.step();
+    mars.load_program(program, 0).unwrap();
+    mars.step().unwrap();
 
     let expected = Instruction {
       b: program[1].b,
@@ -945,8 +1642,8 @@ mod test {
       Instruction::new(Mov, AB, Direct, 1, Direct, target_addr as Address),
       Instruction::new(Nop, F, Immediate, 100, Immediate, 100),
     ];
-    mars.load_program(program, 0);
-    mars.step();
+    mars.load_program(program, 0).unwrap();
+    mars.step().unwrap();
 
     let expected = Instruction {
       b: program[1].a,
@@ -964,8 +1661,8 @@ mod test {
       Instruction::new(Mov, BA, Direct, 1, Direct, target_addr as Address),
       Instruction::new(Nop, F, Immediate, 100, Immediate, 100),
     ];
-    mars.load_program(program, 0);
-    mars.step();
+    mars.load_program(program, 0).unwrap();
+    mars.step().unwrap();
 
     let expected = Instruction {
       a: program[1].b,
@@ -983,8 +1680,8 @@ mod test {
       Instruction::new(Mov, F, Direct, 1, Direct, target_addr as Address),
       Instruction::new(Nop, F, Immediate, 100, Immediate, 100),
     ];
-    mars.load_program(program, 0);
-    mars.step();
+    mars.load_program(program, 0).unwrap();
+    mars.step().unwrap();
 
     let expected = Instruction {
       a: program[1].a,
@@ -1003,8 +1700,8 @@ mod test {
       Instruction::new(Mov, I, Direct, 1, Direct, target_addr as Address),
       Instruction::new(Nop, F, Immediate, 100, Immediate, 100),
     ];
-    mars.load_program(program, 0);
-    mars.step();
+    mars.load_program(program, 0).unwrap();
+    mars.step().unwrap();
 
     let expected = program[1];
     assert_eq!(expected, mars.memory()[expected_addr])
@@ -1019,8 +1716,8 @@ mod test {
       Instruction::new(Mov, X, Direct, 1, Direct, target_addr as Address),
       Instruction::new(Nop, F, Immediate, 100, Immediate, 100),
     ];
-    mars.load_program(program, 0);
-    mars.step();
+    mars.load_program(program, 0).unwrap();
+    mars.step().unwrap();
 
     let expected = Instruction {
       a: program[1].b,
@@ -1035,31 +1732,30 @@ mod test {
   #[test]
   fn processes_switching() {
     let mut mars = Mars::default();
-    mars.load_program(IMP, 1);
-    mars.load_program(&[Instruction::default()], 5); // will kill thread
-    mars.load_program(IMP, 10);
+    mars.load_program(IMP, 1).unwrap();
+    mars.load_program(&[Instruction::default()], 5).unwrap(); // will kill thread
+    mars.load_program(IMP, 10).unwrap();
 
-    assert!(mars.processes().nth(0).unwrap().2.iter().nth(0).unwrap() == &1);
-    assert!(mars.processes().nth(1).unwrap().2.iter().nth(0).unwrap() == &5);
-    assert!(mars.processes().nth(2).unwrap().2.iter().nth(0).unwrap() == &10);
+    assert!(mars.processes().next().unwrap().2.front().unwrap() == &1);
+    assert!(mars.processes().nth(1).unwrap().2.front().unwrap() == &5);
+    assert!(mars.processes().nth(2).unwrap().2.front().unwrap() == &10);
 
-    mars.step();
+    mars.step().unwrap();
 
     // first inline should move to the back after being incremented
-    assert!(mars.processes().nth(0).unwrap().2.iter().nth(0).unwrap() == &5);
-    assert!(mars.processes().nth(1).unwrap().2.iter().nth(0).unwrap() == &10);
-    assert!(mars.processes().nth(2).unwrap().2.iter().nth(0).unwrap() == &2);
+    assert!(mars.processes().next().unwrap().2.front().unwrap() == &5);
+    assert!(mars.processes().nth(1).unwrap().2.front().unwrap() == &10);
+    assert!(mars.processes().nth(2).unwrap().2.front().unwrap() == &2);
 
-    mars.step();
+    mars.step().unwrap();
 
     // first inline should move. The process that executed a dat should have
     // been killed
-    assert!(mars.processes().nth(0).unwrap().2.iter().nth(0).unwrap() == &10);
-    assert!(mars.processes().nth(1).unwrap().2.iter().nth(0).unwrap() == &2);
+    assert!(mars.processes().next().unwrap().2.front().unwrap() == &10);
+    assert!(mars.processes().nth(1).unwrap().2.front().unwrap() == &2);
     assert!(mars.processes().nth(2).is_none());
   }
 
-  #[ignore]
   #[test]
   fn loading_creates_new_queue() {
     let addr1 = 0;
@@ -1068,11 +1764,15 @@ mod test {
     let addr4 = 3000;
 
     let program = [Instruction::default(); 1000];
-    let mut mars = Mars::default();
-    let pid1 = mars.load_program(&program, addr1);
-    let pid2 = mars.load_program(&program, addr2);
-    let pid3 = mars.load_program(&program, addr3);
-    let pid4 = mars.load_program(&program, addr4);
+    let mut mars = MarsBuilder::new(8000)
+      .max_length(1000)
+      .min_distance(1000)
+      .build()
+      .unwrap();
+    let pid1 = mars.load_program(&program, addr1).unwrap();
+    let pid2 = mars.load_program(&program, addr2).unwrap();
+    let pid3 = mars.load_program(&program, addr3).unwrap();
+    let pid4 = mars.load_program(&program, addr4).unwrap();
 
     let expected_queue = {
       let mut q = VecDeque::new();
@@ -1083,7 +1783,13 @@ mod test {
 
       q
     };
-    // TODO: implement me
+    // every process gets its own fresh P-space, so only compare the threads
+    let queue: VecDeque<_> = mars
+      .processes
+      .iter()
+      .map(|&(pid, _, ref threads)| (pid, Vec::<Address>::new(), threads.clone()))
+      .collect();
+    assert_eq!(queue, expected_queue);
   }
 
   #[test]
@@ -1091,7 +1797,7 @@ mod test {
     let add = Instruction::new(Add, I, Direct, 100, Direct, 100);
     let program = [add, add, add];
     let mut mars = Mars::default();
-    mars.load_program(&program, 7999);
+    mars.load_program(&program, 7999).unwrap();
     assert!(mars.memory()[7998] == Instruction::default());
     assert!(mars.memory()[7999] == add);
     assert!(mars.memory()[0] == add);
@@ -1136,4 +1842,616 @@ mod test {
     let mars = you_know_what_it_is(&program, 0);
     assert_eq!(mars.pc(), Some(5));
   }
+
+  #[test]
+  fn stp_then_ldp() {
+    let program = [
+      Instruction::new(Stp, AB, Immediate, 5, Immediate, 3),
+      Instruction::new(Ldp, AB, Immediate, 3, Direct, 1),
+      Instruction::new(Dat, F, Immediate, 0, Immediate, 0),
+    ];
+    let mut mars = Mars::default();
+    mars.load_program(&program, 0).unwrap();
+    mars.step().unwrap();
+    mars.step().unwrap();
+
+    assert_eq!(mars.process_pspaces().next().unwrap().1[3], 5);
+    assert_eq!(mars.memory()[2].b.value, 5);
+  }
+
+  #[test]
+  fn p_space_index_is_folded() {
+    let program = [Instruction::new(Stp, AB, Immediate, 7, Immediate, 11)];
+    let mars = you_know_what_it_is(&program, 0);
+    assert_eq!(mars.process_pspaces().next().unwrap().1[3], 7);
+  }
+
+  #[test]
+  fn p_space_result_cell_starts_negative() {
+    let mut mars = Mars::default();
+    mars.load_program(IMP, 0).unwrap();
+    let (_, pspace) = mars.process_pspaces().next().unwrap();
+    assert_eq!(pspace.len(), MARS_DEFAULT_P_SPACE_SIZE);
+    assert_eq!(pspace[0], mars.size() as Address - 1);
+  }
+
+  #[test]
+  fn p_space_size_from_builder() {
+    let mut mars = MarsBuilder::new(800).p_space_size(16).build().unwrap();
+    mars.load_program(IMP, 0).unwrap();
+    assert_eq!(mars.p_space_size(), 16);
+    assert_eq!(mars.process_pspaces().next().unwrap().1.len(), 16);
+  }
+
+  #[test]
+  fn shared_p_space() {
+    let store: &[Instruction] = &[Instruction::new(Stp, AB, Immediate, 9, Immediate, 2)];
+    let load: &[Instruction] = &[Instruction::new(Ldp, AB, Immediate, 2, Direct, 1)];
+    let mut mars = Mars::default();
+    mars
+      .load_programs_with_shared_pspace(&[store, load], &[0, 100])
+      .unwrap();
+    mars.step().unwrap();
+    mars.step().unwrap();
+
+    assert_eq!(mars.memory()[101].b.value, 9);
+    for (_, pspace) in mars.process_pspaces() {
+      assert_eq!(pspace[2], 9);
+    }
+  }
+
+  #[test]
+  fn warrior_starts_at_start_offset() {
+    let warrior = Warrior {
+      instructions: vec![Instruction::default(), IMP[0]],
+      start: 1,
+      ..Warrior::default()
+    };
+    let mut mars = Mars::default();
+    mars.load_warrior(&warrior, 7999).unwrap();
+    assert_eq!(mars.pc(), Some(0));
+    mars.step().unwrap();
+    assert_eq!(mars.memory()[1], IMP[0]);
+  }
+
+  #[test]
+  fn spl_queues_next_instruction_first() {
+    let mut mars = Mars::default();
+    mars
+      .load_program(&[Instruction::new(Spl, B, Direct, 2, Direct, 0)], 0)
+      .unwrap();
+    mars.step().unwrap();
+    assert_eq!(
+      mars.processes().next().unwrap().2,
+      VecDeque::from(vec![1, 2])
+    );
+  }
+
+  #[test]
+  fn spl_respects_max_processes() {
+    let mut mars = MarsBuilder::new(800).max_processes(3).build().unwrap();
+    let program = [
+      Instruction::new(Spl, B, Direct, 0, Direct, 0),
+      Instruction::new(Jmp, B, Direct, 799, Direct, 0),
+    ];
+    mars.load_program(&program, 0).unwrap();
+    for _ in 0..10 {
+      mars.step().unwrap();
+      assert!(mars.thread_count().all(|(_, count)| count <= 3));
+    }
+    assert_eq!(mars.thread_count().next(), Some((0, 3)));
+    assert_eq!(Mars::default().max_processes(), MARS_DEFAULT_MAX_PROCESSES);
+  }
+
+  #[test]
+  fn environment_from_builder() {
+    let builder = MarsBuilder::new(800).p_space_size(16).max_processes(64);
+    let env = Environment::from(&builder);
+    assert_eq!(env.core_size, 800);
+    assert_eq!(env.p_space_size, 16);
+    assert_eq!(env.max_processes, 64);
+    assert_eq!(env.max_cycles, MARS_DEFAULT_MAX_CYCLES);
+  }
+
+  #[test]
+  fn builder_validation() {
+    let cases = [
+      (MarsBuilder::new(0), MarsError::EmptyCore),
+      (
+        MarsBuilder::new(800).p_space_size(30),
+        MarsError::InvalidPSpaceSize {
+          p_space_size: 30,
+          size: 800,
+        },
+      ),
+      (
+        MarsBuilder::new(800).read_limit(0),
+        MarsError::InvalidReadLimit {
+          limit: 0,
+          size: 800,
+        },
+      ),
+      (
+        MarsBuilder::new(800).write_limit(300),
+        MarsError::InvalidWriteLimit {
+          limit: 300,
+          size: 800,
+        },
+      ),
+      (
+        MarsBuilder::new(800).max_processes(0),
+        MarsError::NoProcesses,
+      ),
+      (MarsBuilder::new(800).max_cycles(0), MarsError::NoCycles),
+      (
+        MarsBuilder::new(80),
+        MarsError::InvalidMaxLength {
+          max_length: 100,
+          size: 80,
+        },
+      ),
+      (
+        MarsBuilder::new(800).min_distance(50),
+        MarsError::InvalidMinDistance {
+          min_distance: 50,
+          max_length: 100,
+        },
+      ),
+    ];
+
+    for (builder, error) in cases.iter() {
+      assert_eq!(builder.build().unwrap_err(), *error);
+    }
+
+    let mars = MarsBuilder::default().build().unwrap();
+    assert_eq!(mars.size(), MARS_DEFAULT_SIZE);
+    assert_eq!(mars.max_cycles(), MARS_DEFAULT_MAX_CYCLES);
+    assert_eq!(mars.read_limit(), MARS_DEFAULT_SIZE);
+
+    let builder = MarsBuilder::new(800).write_limit(400).reversible(true);
+    assert_eq!(builder.build().unwrap().builder(), builder);
+  }
+
+  #[test]
+  fn long_programs_are_rejected() {
+    let mut mars = MarsBuilder::new(800)
+      .max_length(10)
+      .min_distance(10)
+      .build()
+      .unwrap();
+    let long = vec![Instruction::default(); 11];
+    let error = MarsError::ProgramTooLong {
+      length: 11,
+      max_length: 10,
+    };
+
+    assert_eq!(mars.load_program(&long, 0), Err(error));
+    assert_eq!(
+      mars.load_programs_with_shared_pspace(&[IMP, &long], &[0, 400]),
+      Err(error)
+    );
+    assert_eq!(mars.process_count(), 0);
+    assert!(mars.load_program(&long[..10], 0).is_ok());
+  }
+
+  #[test]
+  fn write_limit_folds_addresses() {
+    let mut mars = MarsBuilder::new(800).write_limit(400).build().unwrap();
+    // 300 is folded to 100 behind the program counter
+    mars
+      .load_program(&[Instruction::new(Mov, I, Direct, 0, Direct, 300)], 10)
+      .unwrap();
+    mars.step().unwrap();
+    assert_eq!(mars.memory()[710].op.code, Mov);
+    assert_eq!(mars.memory()[310], Instruction::default());
+  }
+
+  #[test]
+  fn post_increment_wraps() {
+    let program = ::parse::parse_program("JMP 1\nMOV 0, >-1\nDAT }-2, 0").unwrap();
+    let mut mars = Mars::default();
+    mars.load_program(&program.instructions, 4000).unwrap();
+    for _ in 0..3 {
+      mars.step().unwrap();
+    }
+    // the pointer at 4000 was overwritten by the MOV and its B field
+    // incremented past the end of the core, then the DAT incremented its A
+    // field before dying
+    assert_eq!(mars.memory()[4000].op.code, Mov);
+    assert_eq!(mars.memory()[4000].b.value, 0);
+    assert_eq!(mars.memory()[4000].a.value, 1);
+    assert_eq!(mars.process_count(), 0);
+  }
+
+  #[test]
+  fn pre_decrement_wraps() {
+    let mut mars = Mars::default();
+    mars
+      .load_program(
+        &[
+          Instruction::new(Mov, I, BIndirect(IncrementMode::PreDecrement), 2, Direct, 1),
+          Instruction::default(),
+          Instruction::default(),
+        ],
+        100,
+      )
+      .unwrap();
+    mars.step().unwrap();
+    // the B field of 0 is decremented to the end of the core
+    assert_eq!(mars.memory()[102].b.value, 7999);
+
+    let mut mars = Mars::default();
+    let program = [
+      Instruction::new(Mov, I, Direct, 0, BIndirect(IncrementMode::PreDecrement), 1),
+      Instruction::default(),
+    ];
+    mars.load_program(&program, 100).unwrap();
+    assert!(mars.step().is_ok());
+  }
+
+  #[test]
+  fn read_limit_folds_b_reads() {
+    let mut mars = MarsBuilder::new(800).read_limit(400).build().unwrap();
+    mars.set_memory(&[Instruction::new(Dat, F, Direct, 7, Direct, 0)], 710);
+    mars.set_memory(&[Instruction::new(Dat, F, Direct, 100, Direct, 0)], 310);
+    // 300 is read 100 behind the program counter and written unfolded
+    mars
+      .load_program(&[Instruction::new(Add, A, Immediate, 5, Direct, 300)], 10)
+      .unwrap();
+    mars.step().unwrap();
+    assert_eq!(mars.memory()[310].a.value, 12);
+    assert_eq!(mars.memory()[710].a.value, 7);
+  }
+
+  #[test]
+  fn cycles_are_counted_per_process() {
+    let mut mars = MarsBuilder::new(800).max_cycles(10).build().unwrap();
+    mars.load_program(&[Instruction::default()], 0).unwrap();
+    mars.load_program(IMP, 200).unwrap();
+    mars.load_program(IMP, 400).unwrap();
+    assert_eq!(mars.cycles_left(), 30);
+
+    // the two imps keep the 20 turns they had left after the DAT dies
+    let outcome = mars.step().unwrap();
+    assert_eq!(outcome.pid, 0);
+    assert!(outcome.process_killed);
+    assert_eq!(mars.cycles_left(), 20);
+    for _ in 0..20 {
+      mars.step().unwrap();
+    }
+    assert_eq!(mars.cycles_left(), 0);
+    assert_eq!(mars.process_count(), 2);
+  }
+
+  #[test]
+  fn cell_tracking() {
+    assert!(Mars::default().cell_history().is_none());
+
+    let mut mars = MarsBuilder::new(800).track_cells(true).build().unwrap();
+    // bomb the cell 4 past the DAT, like the Dwarf
+    let pid = mars
+      .load_program(
+        &[
+          Instruction::new(Add, B, Immediate, 4, Direct, 3),
+          Instruction::new(Mov, I, Direct, 2, BIndirect(IncrementMode::None), 2),
+          Instruction::new(Jmp, B, Direct, 798, Direct, 0),
+          Instruction::new(Dat, F, Immediate, 0, Immediate, 0),
+        ],
+        0,
+      )
+      .unwrap();
+    let loaded = Some(Access { pid, cycle: 0 });
+    let first = Some(Access { pid, cycle: 1 });
+    let second = Some(Access { pid, cycle: 2 });
+    mars.step().unwrap();
+    mars.step().unwrap();
+
+    let cells = mars.cell_history().unwrap();
+    assert_eq!(cells[0].execute, first);
+    assert_eq!(cells[0].read, None);
+    assert_eq!(cells[2].write, loaded);
+    assert_eq!(cells[3].write, first);
+    assert_eq!(cells[3].read, second);
+    assert_eq!(cells[1].execute, second);
+    assert_eq!(cells[7].write, second);
+    assert_eq!(cells[2].read, None);
+    assert_eq!(cells[8], CellHistory::default());
+
+    mars.reset();
+    assert!(mars
+      .cell_history()
+      .unwrap()
+      .iter()
+      .all(|cell| *cell == CellHistory::default()));
+  }
+
+  #[test]
+  fn step_emits_events() {
+    let mut mars = MarsBuilder::new(800).build().unwrap();
+    let program = [
+      Instruction::new(Spl, B, Direct, 2, Direct, 0),
+      Instruction::default(),
+      Instruction::new(Stp, AB, Immediate, 5, Immediate, 1),
+    ];
+    mars.load_program(&program, 0).unwrap();
+    mars.load_program(IMP, 400).unwrap();
+    mars.load_program(&[Instruction::default()], 200).unwrap();
+
+    let mut events = vec![];
+    for _ in 0..5 {
+      mars.step_with(&mut events).unwrap();
+    }
+
+    let executed = |pid, cycle, pc, instruction, a_pointer, b_pointer| Event::InstructionExecuted {
+      pid,
+      cycle,
+      pc,
+      instruction,
+      a_pointer,
+      b_pointer,
+    };
+    assert_eq!(
+      events,
+      vec![
+        executed(0, 1, 0, program[0], 2, 0),
+        Event::ThreadSplit { pid: 0, address: 2 },
+        executed(1, 2, 400, IMP[0], 400, 401),
+        Event::MemoryWritten {
+          pid: 1,
+          address: 401,
+          old: Instruction::default(),
+          new: IMP[0],
+        },
+        executed(2, 3, 200, Instruction::default(), 200, 200),
+        Event::ThreadKilled { pid: 2, pc: 200 },
+        Event::ProcessKilled { pid: 2 },
+        executed(0, 4, 1, Instruction::default(), 1, 1),
+        Event::ThreadKilled { pid: 0, pc: 1 },
+        executed(1, 5, 401, IMP[0], 401, 402),
+        Event::MemoryWritten {
+          pid: 1,
+          address: 402,
+          old: Instruction::default(),
+          new: IMP[0],
+        },
+      ]
+    );
+
+    let mut events = vec![];
+    mars.step_with(&mut events).unwrap();
+    assert_eq!(
+      events[1],
+      Event::PSpaceAccess {
+        pid: 0,
+        kind: PSpaceAccessKind::Store,
+        index: 1,
+        value: 5,
+      }
+    );
+    let (_, pspace) = mars.process_pspaces().find(|&(pid, _)| pid == 0).unwrap();
+    assert_eq!(pspace[1], 5);
+  }
+
+  #[test]
+  fn trace_lines() {
+    let mut mars = MarsBuilder::new(800).build().unwrap();
+    mars.load_program(IMP, 10).unwrap();
+    mars.load_program(&[Instruction::default()], 400).unwrap();
+
+    let trace = |format| {
+      let mut mars = mars.clone();
+      let mut tracer = Tracer::new(vec![], format);
+      for _ in 0..2 {
+        mars.step_with(&mut tracer).unwrap();
+      }
+      String::from_utf8(tracer.finish().unwrap()).unwrap()
+    };
+
+    let (imp, dat) = (IMP[0], Instruction::default());
+    assert_eq!(
+      trace(TraceFormat::Text),
+      format!(
+        "1 0 10 {} | 10 11 | 11 {}\n2 1 400 {} | 400 400\n",
+        imp, imp, dat
+      )
+    );
+    assert_eq!(
+      trace(TraceFormat::JsonLines).lines().next().unwrap(),
+      format!(
+        "{{\"cycle\":1,\"pid\":0,\"pc\":10,\"instruction\":\"{}\",\"a_pointer\":10,\
+         \"b_pointer\":11,\"changed\":[{{\"address\":11,\"instruction\":\"{}\"}}]}}",
+        imp, imp
+      )
+    );
+  }
+
+  #[test]
+  fn step_outcome() {
+    let mut mars = MarsBuilder::new(800).build().unwrap();
+    assert_eq!(mars.step(), Err(MarsError::NoProcessesLeft));
+
+    let spl = Instruction::new(Spl, B, Direct, 2, Direct, 0);
+    mars
+      .load_program(&[spl, Instruction::default()], 10)
+      .unwrap();
+    assert_eq!(
+      mars.step(),
+      Ok(StepOutcome {
+        pid: 0,
+        pc: 10,
+        instruction: spl,
+        next_pc: Some(11),
+        split: Some(12),
+        process_killed: false,
+      })
+    );
+
+    let outcome = mars.step().unwrap();
+    assert!(outcome.thread_killed());
+    assert!(!outcome.process_killed);
+    assert!(mars.step().unwrap().process_killed);
+    assert_eq!(mars.step(), Err(MarsError::NoProcessesLeft));
+  }
+
+  #[test]
+  fn snapshot_and_restore() {
+    // stores the cycle count in P-space and bombs ahead of itself
+    let program = [
+      Instruction::new(Stp, AB, Immediate, 7, Immediate, 1),
+      Instruction::new(Mov, I, Direct, 0, Direct, 20),
+      Instruction::new(Spl, B, Direct, 0, Direct, 0),
+    ];
+    let mut mars = MarsBuilder::new(800).build().unwrap();
+    mars
+      .load_programs_with_shared_pspace(&[&program, IMP], &[0, 400])
+      .unwrap();
+    let dat = mars.load_program(&[Instruction::default()], 200).unwrap();
+
+    let snapshot = mars.snapshot();
+    let memory = mars.memory().to_vec();
+    let queues: Vec<Vec<Address>> = mars
+      .process_queues()
+      .map(|(_, queue)| queue.cloned().collect())
+      .collect();
+    for _ in 0..12 {
+      mars.step().unwrap();
+    }
+    assert_ne!(mars.memory(), memory.as_slice());
+    assert_eq!(mars.process_pspaces().next().unwrap().1[1], 7);
+    assert!(mars.pids().all(|pid| pid != dat));
+
+    mars.restore(&snapshot);
+    assert_eq!(mars.cycle(), snapshot.cycle());
+    assert_eq!(mars.cycle(), 0);
+    assert_eq!(mars.memory(), memory.as_slice());
+    assert_eq!(
+      mars
+        .process_queues()
+        .map(|(_, queue)| queue.cloned().collect::<Vec<_>>())
+        .collect::<Vec<_>>(),
+      queues
+    );
+    assert!(mars.process_pspaces().all(|(_, pspace)| pspace[1] == 0));
+
+    // the warriors of the team still share a P-space
+    let pspaces: Vec<PSpace> = mars
+      .processes()
+      .filter(|&&(pid, _, _)| pid != dat)
+      .map(|(_, pspace, _)| pspace.clone())
+      .collect();
+    assert!(Rc::ptr_eq(&pspaces[0], &pspaces[1]));
+    mars.step().unwrap();
+    assert_eq!(pspaces[0].borrow()[1], 7);
+  }
+
+  #[test]
+  fn clones_copy_pspaces() {
+    let store = [Instruction::new(Stp, AB, Immediate, 9, Immediate, 2)];
+    let mut mars = MarsBuilder::new(800).build().unwrap();
+    mars
+      .load_programs_with_shared_pspace(&[&store, IMP], &[0, 400])
+      .unwrap();
+
+    let mut clone = mars.clone();
+    clone.step().unwrap();
+    assert_eq!(clone.process_pspaces().next().unwrap().1[2], 9);
+    assert!(mars.process_pspaces().all(|(_, pspace)| pspace[2] == 0));
+
+    // the team still shares a P-space in the clone
+    let pspaces: Vec<PSpace> = clone
+      .processes()
+      .map(|(_, pspace, _)| pspace.clone())
+      .collect();
+    assert!(Rc::ptr_eq(&pspaces[0], &pspaces[1]));
+  }
+
+  #[test]
+  fn step_back_undoes_steps() {
+    type State = (
+      Vec<Instruction>,
+      usize,
+      usize,
+      Vec<(Pid, Vec<Address>, Vec<Address>)>,
+      Vec<CellHistory>,
+    );
+    fn state(mars: &Mars) -> State {
+      (
+        mars.memory().to_vec(),
+        mars.cycle(),
+        mars.cycles_left(),
+        mars
+          .processes()
+          .map(|(pid, pspace, threads)| {
+            (
+              *pid,
+              pspace.borrow().clone(),
+              threads.iter().cloned().collect(),
+            )
+          })
+          .collect(),
+        mars.cell_history().unwrap().to_vec(),
+      )
+    }
+
+    let mut mars = MarsBuilder::new(800)
+      .max_cycles(50)
+      .track_cells(true)
+      .reversible(true)
+      .build()
+      .unwrap();
+    let bomber = [
+      Instruction::new(Spl, B, Direct, 2, Direct, 0),
+      Instruction::new(Stp, AB, Immediate, 7, Immediate, 1),
+      Instruction::new(Add, B, Immediate, 4, Direct, 2),
+      Instruction::new(Mov, I, Direct, 2, BIndirect(IncrementMode::None), 1),
+      Instruction::new(Jmp, B, Direct, 798, Direct, 0),
+      Instruction::default(),
+    ];
+    mars.load_program(&bomber, 0).unwrap();
+    mars.load_program(IMP, 400).unwrap();
+    mars.load_program(&[Instruction::default()], 200).unwrap();
+    assert!(mars.is_reversible());
+    assert_eq!(mars.step_back(), None);
+
+    let mut states = vec![state(&mars)];
+    let mut outcomes = vec![];
+    for _ in 0..40 {
+      outcomes.push(mars.step().unwrap());
+      states.push(state(&mars));
+    }
+    assert_eq!(mars.steps_recorded(), 40);
+
+    states.pop();
+    while let Some(expected) = states.pop() {
+      assert_eq!(mars.step_back(), outcomes.pop());
+      assert_eq!(state(&mars), expected);
+    }
+    assert_eq!(mars.step_back(), None);
+
+    // writing to memory can't be undone
+    mars.step().unwrap();
+    mars.set_memory(&[Instruction::default()], 10);
+    assert_eq!(mars.steps_recorded(), 0);
+    assert!(!Mars::default().is_reversible());
+  }
+
+  #[test]
+  fn undo_log_is_bounded() {
+    let builder = MarsBuilder::new(800).reversible(true).undo_limit(5);
+    let mut mars = builder.build().unwrap();
+    assert_eq!(mars.builder(), builder);
+    mars.load_program(IMP, 0).unwrap();
+
+    for _ in 0..20 {
+      mars.step().unwrap();
+    }
+    assert_eq!(mars.steps_recorded(), 5);
+
+    // only the latest steps can be undone
+    for pc in (15..20).rev() {
+      assert_eq!(mars.step_back().unwrap().pc, pc);
+    }
+    assert_eq!(mars.step_back(), None);
+    assert_eq!(mars.cycle(), 15);
+  }
 }
@@ -0,0 +1,77 @@
+//! Checkpoints of the state of a `Mars`
+use {
+  super::{CellHistory, Mars, PSpace, Process},
+  redcode::{Address, Instruction},
+  std::rc::Rc,
+};
+
+/// The state of a `Mars` at some cycle, see `Mars::snapshot`
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+  memory: Vec<Instruction>,
+  cycle: usize,
+  cycles_left: usize,
+  /// Processes along with handles to their P-spaces
+  processes: Vec<Process>,
+  /// Contents of every distinct P-space
+  pspaces: Vec<(PSpace, Vec<Address>)>,
+  cells: Option<Vec<CellHistory>>,
+}
+
+impl Snapshot {
+  /// Return the cycle the snapshot was taken in
+  pub fn cycle(&self) -> usize {
+    self.cycle
+  }
+}
+
+impl Mars {
+  /// Capture the memory, cycle counter, process and thread queues and the
+  /// contents of every P-space
+  pub fn snapshot(&self) -> Snapshot {
+    let mut pspaces: Vec<(PSpace, Vec<Address>)> = vec![];
+    for (_, pspace, _) in &self.processes {
+      // processes may share a P-space, which only needs saving once
+      if !pspaces.iter().any(|(saved, _)| Rc::ptr_eq(saved, pspace)) {
+        pspaces.push((pspace.clone(), pspace.borrow().clone()));
+      }
+    }
+
+    Snapshot {
+      memory: self.memory.clone(),
+      cycle: self.cycle,
+      cycles_left: self.cycles_left,
+      processes: self.processes.iter().cloned().collect(),
+      pspaces,
+      cells: self.cells.clone(),
+    }
+  }
+
+  /// Return to the state captured by `snapshot`
+  ///
+  /// P-spaces are restored in place, so processes that shared a P-space
+  /// still do, and handles to P-spaces held elsewhere, like a `Match`'s, see
+  /// the restored contents. Restoring into another `Mars`, like a clone,
+  /// makes it share the P-spaces of the `Mars` the snapshot was taken of.
+  /// Steps recorded for `step_back` are forgotten
+  ///
+  /// # Panics
+  /// panics if the snapshot was taken of a core of a different size
+  pub fn restore(&mut self, snapshot: &Snapshot) {
+    assert_eq!(
+      self.memory.len(),
+      snapshot.memory.len(),
+      "cannot restore a snapshot of a core of a different size"
+    );
+
+    self.memory.copy_from_slice(&snapshot.memory);
+    self.cycle = snapshot.cycle;
+    self.cycles_left = snapshot.cycles_left;
+    self.processes = snapshot.processes.iter().cloned().collect();
+    for (pspace, contents) in &snapshot.pspaces {
+      pspace.borrow_mut().clone_from(contents);
+    }
+    self.cells = snapshot.cells.clone();
+    self.clear_undo();
+  }
+}
@@ -0,0 +1,147 @@
+//! Line-oriented traces of every executed instruction, for comparing runs with
+//! other simulators
+use {
+  super::{Event, Observer, Pid},
+  redcode::{Address, Instruction},
+  std::io::{self, Write},
+};
+
+/// How each line of a trace is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+  /// `cycle pid pc instruction | a_pointer b_pointer`, followed by
+  /// `| address instruction` for every cell that changed, e.g.
+  /// `3 0 100 MOV.I $0 $1 | 100 101 | 101 MOV.I $0 $1`
+  Text,
+  /// A JSON object per line with the fields `cycle`, `pid`, `pc`,
+  /// `instruction`, `a_pointer`, `b_pointer` and `changed`, a list of objects
+  /// with the fields `address` and `instruction`
+  JsonLines,
+}
+
+/// An executed instruction and the cells it changed
+#[derive(Debug, Clone)]
+struct TracedStep {
+  cycle: usize,
+  pid: Pid,
+  pc: Address,
+  instruction: Instruction,
+  a_pointer: Address,
+  b_pointer: Address,
+  changed: Vec<(Address, Instruction)>,
+}
+
+/// An `Observer` that writes a line for every executed instruction
+///
+/// The line of a step is written once every event of the step was seen, which
+/// is when the next instruction is executed or when the tracer is finished
+#[derive(Debug)]
+pub struct Tracer<W: Write> {
+  writer: W,
+  format: TraceFormat,
+  step: Option<TracedStep>,
+  /// The first error writing the trace, after which nothing else is written
+  error: Option<io::Error>,
+}
+
+impl<W: Write> Tracer<W> {
+  pub fn new(writer: W, format: TraceFormat) -> Self {
+    Tracer {
+      writer,
+      format,
+      step: None,
+      error: None,
+    }
+  }
+
+  /// Write the line of the last step and return the writer
+  ///
+  /// # Errors
+  /// Returns the first error writing the trace
+  pub fn finish(mut self) -> io::Result<W> {
+    self.write_step();
+    if let Some(error) = self.error.take() {
+      return Err(error);
+    }
+    self.writer.flush()?;
+    Ok(self.writer)
+  }
+
+  fn write_step(&mut self) {
+    if let Some(step) = self.step.take() {
+      if self.error.is_none() {
+        let written = match self.format {
+          TraceFormat::Text => write_text(&mut self.writer, &step),
+          TraceFormat::JsonLines => write_json(&mut self.writer, &step),
+        };
+        self.error = written.err();
+      }
+    }
+  }
+}
+
+impl<W: Write> Observer for Tracer<W> {
+  fn observe(&mut self, event: &Event) {
+    match *event {
+      Event::InstructionExecuted {
+        pid,
+        cycle,
+        pc,
+        instruction,
+        a_pointer,
+        b_pointer,
+      } => {
+        self.write_step();
+        self.step = Some(TracedStep {
+          cycle,
+          pid,
+          pc,
+          instruction,
+          a_pointer,
+          b_pointer,
+          changed: vec![],
+        });
+      }
+      Event::MemoryWritten { address, new, .. } => {
+        if let Some(ref mut step) = self.step {
+          step.changed.push((address, new));
+        }
+      }
+      _ => {}
+    }
+  }
+}
+
+fn write_text<W: Write>(writer: &mut W, step: &TracedStep) -> io::Result<()> {
+  write!(
+    writer,
+    "{} {} {} {} | {} {}",
+    step.cycle, step.pid, step.pc, step.instruction, step.a_pointer, step.b_pointer
+  )?;
+  for (address, instruction) in &step.changed {
+    write!(writer, " | {} {}", address, instruction)?;
+  }
+  writeln!(writer)
+}
+
+fn write_json<W: Write>(writer: &mut W, step: &TracedStep) -> io::Result<()> {
+  // instructions are displayed without quotes or backslashes, so they don't
+  // need escaping
+  write!(
+    writer,
+    "{{\"cycle\":{},\"pid\":{},\"pc\":{},\"instruction\":\"{}\",\"a_pointer\":{},\
+     \"b_pointer\":{},\"changed\":[",
+    step.cycle, step.pid, step.pc, step.instruction, step.a_pointer, step.b_pointer
+  )?;
+  for (i, (address, instruction)) in step.changed.iter().enumerate() {
+    if i > 0 {
+      write!(writer, ",")?;
+    }
+    write!(
+      writer,
+      "{{\"address\":{},\"instruction\":\"{}\"}}",
+      address, instruction
+    )?;
+  }
+  writeln!(writer, "]}}")
+}
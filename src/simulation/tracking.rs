@@ -0,0 +1,77 @@
+//! Optional record of the processes that last accessed each cell of the core
+use {
+  super::Pid,
+  redcode::{Address, AddressingMode, AddressingMode::*, IncrementMode, Instruction, OpCode::*},
+};
+
+/// An access to a cell by a process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+  pub pid: Pid,
+  /// Cycle the access happened in
+  pub cycle: usize,
+}
+
+/// The last accesses to a cell of the core. Loading a program counts as a
+/// write by its process
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CellHistory {
+  pub write: Option<Access>,
+  pub read: Option<Access>,
+  pub execute: Option<Access>,
+}
+
+/// Record the accesses made by executing the instruction at `pc`
+///
+/// # Params
+/// * `a_target`: address the A operand resolved to
+/// * `b_read`: address the B operand resolved to when folded by the read limit
+/// * `b_written`: address the B operand resolved to when folded by the write
+///   limit, if it was written to, which instructions that divide by zero may
+///   not do
+pub(super) fn record_step(
+  cells: &mut [CellHistory],
+  access: Access,
+  pc: Address,
+  instruction: &Instruction,
+  a_target: Address,
+  b_read: Address,
+  b_written: Option<Address>,
+) {
+  let size = cells.len() as Address;
+  cells[(pc % size) as usize].execute = Some(access);
+
+  // cells holding indirect pointers are read, and written when incremented
+  for field in &[instruction.a, instruction.b] {
+    let pointer = ((pc + field.value) % size) as usize;
+    match field.mode {
+      AIndirect(increment) | BIndirect(increment) => {
+        cells[pointer].read = Some(access);
+        if increment != IncrementMode::None {
+          cells[pointer].write = Some(access);
+        }
+      }
+      Immediate | Direct => {}
+    }
+  }
+
+  let (reads_a, reads_b, writes_b) = match instruction.op.code {
+    Dat | Jmp | Spl | Nop => (false, false, false),
+    Mov | Ldp => (true, false, true),
+    Add | Sub | Mul | Div | Mod => (true, true, true),
+    Jmz | Jmn => (false, true, false),
+    Djn => (false, true, true),
+    Seq | Sne | Cmp | Slt | Stp => (true, true, false),
+  };
+  let is_operand = |mode: AddressingMode| !mode.is_immediate();
+
+  if reads_a && is_operand(instruction.a.mode) {
+    cells[(a_target % size) as usize].read = Some(access);
+  }
+  if reads_b && is_operand(instruction.b.mode) {
+    cells[(b_read % size) as usize].read = Some(access);
+  }
+  if let (true, Some(b_write)) = (writes_b, b_written) {
+    cells[(b_write % size) as usize].write = Some(access);
+  }
+}
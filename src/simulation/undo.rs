@@ -0,0 +1,87 @@
+//! A log of the changes made by every step, for stepping backwards
+use {
+  super::{CellHistory, Mars, PSpace, StepOutcome},
+  redcode::{Address, Instruction},
+  std::collections::VecDeque,
+};
+
+/// What it takes to undo a step
+#[derive(Debug, Clone)]
+pub(super) struct UndoEntry {
+  pub outcome: StepOutcome,
+  /// `cycles_left` before the step, which can't be recovered after a process
+  /// is killed
+  pub cycles_left: usize,
+  /// The P-space of the process, if the step killed it
+  pub pspace: Option<PSpace>,
+  /// Cells the step changed along with their previous contents
+  pub memory: Vec<(Address, Instruction)>,
+  /// Index and previous value of a P-space cell the step stored to
+  pub pspace_store: Option<(Address, Address)>,
+  /// Previous history of the cells the step accessed, if cells are tracked
+  pub cells: Vec<(Address, CellHistory)>,
+}
+
+impl Mars {
+  /// Undo the last step
+  ///
+  /// # Returns
+  /// The outcome of the step that was undone, or `None` if there are no steps
+  /// to undo. Only a Mars built with `MarsBuilder::reversible` records its
+  /// steps, up to `MarsBuilder::undo_limit` of them, and it forgets them when
+  /// a program is loaded, memory is set, it is reset or a snapshot is
+  /// restored
+  pub fn step_back(&mut self) -> Option<StepOutcome> {
+    let entry = self.undo.as_mut().and_then(VecDeque::pop_back)?;
+    let outcome = entry.outcome;
+
+    for &(address, instruction) in &entry.memory {
+      self.memory[address as usize] = instruction;
+    }
+    if let Some(ref mut cells) = self.cells {
+      for &(address, history) in &entry.cells {
+        cells[address as usize] = history;
+      }
+    }
+
+    let (pid, pspace, mut threads) = match entry.pspace {
+      Some(pspace) => (outcome.pid, pspace, VecDeque::new()),
+      None => self
+        .processes
+        .pop_back()
+        .expect("the process was requeued by the step"),
+    };
+    if let Some((index, value)) = entry.pspace_store {
+      pspace.borrow_mut()[index as usize] = value;
+    }
+    if outcome.split.is_some() {
+      threads.pop_back();
+    }
+    if outcome.next_pc.is_some() {
+      threads.pop_back();
+    }
+    threads.push_front(outcome.pc);
+    self.processes.push_front((pid, pspace, threads));
+
+    self.cycle -= 1;
+    self.cycles_left = entry.cycles_left;
+    Some(outcome)
+  }
+
+  /// Return the number of steps that can be undone
+  pub fn steps_recorded(&self) -> usize {
+    self.undo.as_ref().map_or(0, VecDeque::len)
+  }
+
+  /// Return true if the Mars records its steps so they can be undone
+  pub fn is_reversible(&self) -> bool {
+    self.undo.is_some()
+  }
+
+  /// Forget every recorded step, after a change that steps can't undo
+  pub(super) fn clear_undo(&mut self) {
+    if let Some(ref mut undo) = self.undo {
+      undo.clear();
+    }
+  }
+}
@@ -0,0 +1,279 @@
+//! Round-robin tournaments, where every warrior battles every other warrior
+use {
+  failure::Fail,
+  game::{GameError, Match, Record},
+  parse::{parse_program_with_environment, Environment, ParseError},
+  rand::{rngs::StdRng, Rng, SeedableRng},
+  redcode::Warrior,
+  simulation::{MarsBuilder, MarsError},
+  std::{cmp::Reverse, fmt, fs, io, path::Path},
+};
+
+/// Extensions of the files that `load_warriors` reads
+const WARRIOR_EXTENSIONS: &[&str] = &["red", "redcode"];
+
+#[derive(Debug, Fail)]
+pub enum TournamentError {
+  #[fail(display = "failed to read {}: {}", path, cause)]
+  Io {
+    path: String,
+    #[cause]
+    cause: io::Error,
+  },
+  #[fail(display = "failed to parse {}: {}", path, cause)]
+  Parse {
+    path: String,
+    cause: Box<ParseError>,
+  },
+}
+
+/// Parse every `.red` and `.redcode` file in a directory, in order of their
+/// paths. Warriors without a `;name` are named after their file
+pub fn load_warriors(dir: &Path, env: &Environment) -> Result<Vec<Warrior>, TournamentError> {
+  let io_error = |path: &Path| {
+    let path = path.display().to_string();
+    move |cause| TournamentError::Io { path, cause }
+  };
+
+  let mut paths = vec![];
+  for entry in fs::read_dir(dir).map_err(io_error(dir))? {
+    let path = entry.map_err(io_error(dir))?.path();
+    let is_warrior = path
+      .extension()
+      .and_then(|extension| extension.to_str())
+      .is_some_and(|extension| WARRIOR_EXTENSIONS.contains(&extension));
+    if is_warrior {
+      paths.push(path);
+    }
+  }
+  paths.sort();
+
+  paths
+    .iter()
+    .map(|path| {
+      let source = fs::read_to_string(path).map_err(io_error(path))?;
+      let mut warrior =
+        parse_program_with_environment(&source, env).map_err(|cause| TournamentError::Parse {
+          path: path.display().to_string(),
+          cause: Box::new(cause),
+        })?;
+      if warrior.name.is_none() {
+        warrior.name = path
+          .file_stem()
+          .map(|stem| stem.to_string_lossy().into_owned());
+      }
+      Ok(warrior)
+    })
+    .collect()
+}
+
+/// A warrior's results over a whole tournament
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Score {
+  /// Index of the warrior in the tournament
+  pub warrior: usize,
+  pub name: String,
+  pub record: Record,
+}
+
+/// The results of the match between two warriors, from the point of view of
+/// the `first`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pairing {
+  pub first: usize,
+  pub second: usize,
+  pub record: Record,
+}
+
+/// The results of a tournament
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScoreTable {
+  /// Scores in the order the warriors were added
+  pub scores: Vec<Score>,
+  pub pairings: Vec<Pairing>,
+}
+
+impl ScoreTable {
+  /// Return the scores from the most points to the least. Warriors with the
+  /// same number of points keep the order they were added in
+  pub fn ranking(&self) -> Vec<&Score> {
+    let mut ranking: Vec<&Score> = self.scores.iter().collect();
+    ranking.sort_by_key(|score| Reverse(score.record.points()));
+    ranking
+  }
+}
+
+impl fmt::Display for ScoreTable {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(
+      f,
+      "{:>3}  {:<24} {:>5} {:>5} {:>5} {:>6}",
+      "#", "Name", "W", "L", "T", "Score"
+    )?;
+    for (rank, score) in self.ranking().iter().enumerate() {
+      writeln!(
+        f,
+        "{:>3}  {:<24} {:>5} {:>5} {:>5} {:>6}",
+        rank + 1,
+        score.name,
+        score.record.wins,
+        score.record.losses,
+        score.record.ties,
+        score.record.points()
+      )?;
+    }
+    Ok(())
+  }
+}
+
+/// A round-robin tournament. Every pair of warriors fights a `Match`, where
+/// the first warrior is loaded at 0 and the second at random
+#[derive(Debug, Clone)]
+pub struct Tournament {
+  builder: MarsBuilder,
+  rounds: usize,
+  seed: u64,
+  warriors: Vec<Warrior>,
+}
+
+impl Tournament {
+  /// Create a tournament where each pair of warriors fights `rounds` rounds
+  /// in a `Mars` built by `builder`
+  pub fn new(builder: MarsBuilder, rounds: usize) -> Result<Self, MarsError> {
+    Tournament::with_seed(builder, rounds, ::rand::random())
+  }
+
+  /// Create a tournament that has the same results whenever it is created
+  /// with the same `seed`
+  pub fn with_seed(builder: MarsBuilder, rounds: usize, seed: u64) -> Result<Self, MarsError> {
+    builder.build()?;
+
+    Ok(Tournament {
+      builder,
+      rounds,
+      seed,
+      warriors: vec![],
+    })
+  }
+
+  /// Add a warrior to the tournament
+  ///
+  /// # Returns
+  /// The index of the warrior
+  pub fn add_warrior(&mut self, warrior: Warrior) -> usize {
+    self.warriors.push(warrior);
+    self.warriors.len() - 1
+  }
+
+  pub fn warriors(&self) -> &[Warrior] {
+    &self.warriors
+  }
+
+  /// Fight every pairing of warriors
+  ///
+  /// # Errors
+  /// Fails if there is no room in the core for a pair of warriors, or if a
+  /// warrior is longer than the maximum length
+  pub fn run(&self) -> Result<ScoreTable, GameError> {
+    let mut rng = StdRng::seed_from_u64(self.seed);
+    let mut scores: Vec<Score> = self
+      .warriors
+      .iter()
+      .enumerate()
+      .map(|(i, warrior)| Score {
+        warrior: i,
+        name: warrior
+          .name
+          .clone()
+          .unwrap_or_else(|| format!("Warrior {}", i)),
+        record: Record::default(),
+      })
+      .collect();
+    let mut pairings = vec![];
+
+    for first in 0..self.warriors.len() {
+      for second in first + 1..self.warriors.len() {
+        let mut battle = Match::with_seed(self.builder, self.rounds, rng.gen())
+          .expect("the builder was validated when the tournament was created");
+        let first_pin = battle.add_player_rand(self.warriors[first].clone())?;
+        let second_pin = battle.add_player_rand(self.warriors[second].clone())?;
+        battle.run()?;
+
+        let record = battle.record(first_pin).expect("the player was added");
+        scores[first].record += record;
+        scores[second].record += battle.record(second_pin).expect("the player was added");
+        pairings.push(Pairing {
+          first,
+          second,
+          record,
+        });
+      }
+    }
+
+    Ok(ScoreTable { scores, pairings })
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use redcode::{AddressingMode::*, OpCode::*, OpMode::*, *};
+
+  fn warrior(name: &str, instructions: Vec<Instruction>) -> Warrior {
+    Warrior {
+      instructions,
+      name: Some(name.to_string()),
+      ..Warrior::default()
+    }
+  }
+
+  #[test]
+  fn every_pairing_is_fought() {
+    let imp = vec![Instruction::new(Mov, I, Direct, 0, Direct, 1)];
+    let builder = MarsBuilder::new(800).max_cycles(100);
+    let mut tournament = Tournament::with_seed(builder, 2, 1).unwrap();
+    tournament.add_warrior(warrior("Imp", imp.clone()));
+    tournament.add_warrior(warrior("Dat", vec![Instruction::default()]));
+    tournament.add_warrior(warrior("Other Imp", imp));
+
+    let table = tournament.run().unwrap();
+    assert_eq!(
+      table
+        .pairings
+        .iter()
+        .map(|pairing| (pairing.first, pairing.second))
+        .collect::<Vec<_>>(),
+      vec![(0, 1), (0, 2), (1, 2)]
+    );
+
+    let imp_record = Record {
+      wins: 2,
+      losses: 0,
+      ties: 2,
+    };
+    assert_eq!(table.scores[0].record, imp_record);
+    assert_eq!(table.scores[2].record, imp_record);
+    assert_eq!(table.scores[1].record.losses, 4);
+    assert_eq!(imp_record.points(), 8);
+
+    let ranking: Vec<&str> = table
+      .ranking()
+      .iter()
+      .map(|score| score.name.as_str())
+      .collect();
+    assert_eq!(ranking, vec!["Imp", "Other Imp", "Dat"]);
+    assert!(table
+      .to_string()
+      .contains("  3  Dat                          0     4     0      0"));
+  }
+
+  #[test]
+  fn load_warriors_from_directory() {
+    let warriors = load_warriors(Path::new("programs"), &Environment::default()).unwrap();
+    let names: Vec<_> = warriors
+      .iter()
+      .map(|warrior| warrior.name.clone().unwrap())
+      .collect();
+    assert_eq!(names, vec!["Dwarf", "Imp"]);
+  }
+}